@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::env;
 
 #[link(name = "our_code")]
@@ -6,12 +8,43 @@ extern "C" {
     // it does not add an underscore in front of the name.
     // Courtesy of Max New (https://maxsnew.com/teaching/eecs-483-fa22/hw_adder_assignment.html)
     #[link_name = "\x01our_code_starts_here"]
-    fn our_code_starts_here(input: i64, r15: *mut u64) -> i64;
+    fn our_code_starts_here(input: i64, r15: *mut u64, heap_end: *mut u64, handler: *mut *mut u64) -> i64;
+    // resumes the try handler whose record is at `handler` with `value`
+    #[link_name = "\x01snek_throw"]
+    fn snek_throw(handler: *mut u64, value: i64, heap: *mut u64) -> !;
 }
 
+// heap size in 8-byte words
+const HEAP_SIZE: usize = 100000;
+
+static mut HEAP_START: *mut u64 = 0 as *mut u64;
+static mut HEAP_END: *mut u64 = 0 as *mut u64;
+// name and field names of each struct type, indexed by struct id
+static mut STRUCTS: Vec<Vec<String>> = Vec::new();
+// the innermost try handler record on the snek stack (null when there is
+// none); the compiled code keeps its address in r13
+static mut HANDLER: *mut u64 = 0 as *mut u64;
+// heap pointer of the compiled code, as of the last call into the runtime;
+// a raised error resumes the handler with it
+static mut HEAP_PTR: *mut u64 = 0 as *mut u64;
+
+// Raises the built-in error `errcode`: a try handler catches it as that
+// number, otherwise its message is printed and the program exits.
 #[export_name = "\x01snek_error"]
-pub extern "C" fn snek_error(errcode: i64, code2: i64) {
-    // TODO: print error message according to writeup
+pub unsafe extern "C" fn snek_error(errcode: i64, code2: i64, heap: *mut u64) {
+    HEAP_PTR = heap;
+    raise_error(errcode, code2);
+}
+
+// Resuming a handler jumps straight to it, skipping the destructors of the
+// Rust frames in between, so no caller may own heap data (a Vec or a String)
+// when it raises.
+fn raise_error(errcode: i64, code2: i64) {
+    unsafe {
+        if !HANDLER.is_null() {
+            snek_throw(HANDLER, errcode << 1, HEAP_PTR);
+        }
+    }
     if errcode == 1 {
         eprintln!("invalid argument");
     } else if errcode == 2 {
@@ -20,13 +53,523 @@ pub extern "C" fn snek_error(errcode: i64, code2: i64) {
         eprintln!("index out of bound, {}", code2 / 2);
     } else if errcode == 4 {
         eprintln!("try to index of nil");
+    } else if errcode == 5 {
+        eprintln!("out of memory");
+    } else if errcode == 6 {
+        eprintln!("wrong number of arguments");
+    } else if errcode == 7 {
+        eprintln!("division by zero");
+    } else if errcode == 8 {
+        eprintln!("no pattern matched");
+    } else if errcode == 9 {
+        eprintln!("tuple arity mismatch");
     } else {
         eprintln!("an error ocurred {errcode}");
     }
     std::process::exit(1);
 }
 
-fn parse_input(input: &str) -> i64 {
+#[export_name = "\x01snek_raise"]
+pub unsafe extern "C" fn snek_raise(value: i64, heap: *mut u64) {
+    if !HANDLER.is_null() {
+        snek_throw(HANDLER, value, heap);
+    }
+    eprintln!("uncaught exception: {}", value_to_string(value));
+    std::process::exit(1);
+}
+
+// Heap objects start with a header word: the low byte is the kind of the
+// object and the rest holds its size.
+const KIND_TUPLE: u64 = 0;
+const KIND_CLOSURE: u64 = 1;
+const KIND_STRING: u64 = 2;
+const KIND_BIGNUM: u64 = 3;
+const KIND_FLOAT: u64 = 4;
+// the struct id is kept above the size in the header
+const KIND_STRUCT: u64 = 5;
+
+unsafe fn object_kind(addr: *const u64) -> u64 {
+    *addr & 0xff
+}
+
+// number of words taken by the heap object starting at addr (header included)
+unsafe fn object_size(addr: *const u64) -> usize {
+    let size = (*addr >> 8) as usize;
+    if object_kind(addr) == KIND_STRUCT {
+        return (size & 0xffffff) + 1;
+    }
+    match object_kind(addr) {
+        // header, arity, code pointer, captured values
+        KIND_CLOSURE => size + 3,
+        // header, then the bytes padded to whole words
+        KIND_STRING => (size + 7) / 8 + 1,
+        // header, sign, limbs
+        KIND_BIGNUM => size + 2,
+        _ => size + 1,
+    }
+}
+
+// word offsets of the fields of the object at addr that hold snek values
+unsafe fn object_fields(addr: *const u64) -> std::ops::Range<usize> {
+    match object_kind(addr) {
+        KIND_CLOSURE => 3..object_size(addr),
+        KIND_STRING | KIND_BIGNUM | KIND_FLOAT => object_size(addr)..object_size(addr),
+        _ => 1..object_size(addr),
+    }
+}
+
+// index of the object a tagged value points to, if it is a heap pointer
+fn object_index(objects: &[u64], val: u64) -> Option<usize> {
+    if val & 3 != 1 || val == 1 {
+        return None;
+    }
+    objects.binary_search(&(val - 1)).ok()
+}
+
+// Mark-compact collector. Roots are the words of the snek stack between
+// stack_top and stack_base; any word tagged as a pointer to the start of a
+// heap object is treated as a root and rewritten when the object moves.
+// Returns the new heap pointer; the caller checks whether enough space was freed.
+#[export_name = "\x01snek_gc"]
+pub unsafe extern "C" fn snek_gc(heap_ptr: *mut u64, stack_top: *mut u64, stack_base: *const u64) -> *mut u64 {
+    // every object start, in address order
+    let mut objects = Vec::<u64>::new();
+    let mut addr = HEAP_START;
+    while addr < heap_ptr {
+        objects.push(addr as u64);
+        addr = addr.add(object_size(addr));
+    }
+
+    // mark
+    let mut marked = vec![false; objects.len()];
+    let mut worklist = Vec::<usize>::new();
+    let mut slot = stack_top;
+    while (slot as *const u64) < stack_base {
+        if let Some(k) = object_index(&objects, *slot) {
+            worklist.push(k);
+        }
+        slot = slot.add(1);
+    }
+    while let Some(k) = worklist.pop() {
+        if marked[k] {
+            continue;
+        }
+        marked[k] = true;
+        let obj = objects[k] as *const u64;
+        for j in object_fields(obj) {
+            if let Some(c) = object_index(&objects, *obj.add(j)) {
+                if !marked[c] {
+                    worklist.push(c);
+                }
+            }
+        }
+    }
+
+    // compute forwarding addresses
+    let mut forward = vec![0u64; objects.len()];
+    let mut free = HEAP_START;
+    for k in 0..objects.len() {
+        if marked[k] {
+            forward[k] = free as u64;
+            free = free.add(object_size(objects[k] as *const u64));
+        }
+    }
+
+    // update the stack and the fields of live objects
+    let mut slot = stack_top;
+    while (slot as *const u64) < stack_base {
+        if let Some(k) = object_index(&objects, *slot) {
+            *slot = forward[k] + 1;
+        }
+        slot = slot.add(1);
+    }
+    for k in 0..objects.len() {
+        if !marked[k] {
+            continue;
+        }
+        let obj = objects[k] as *mut u64;
+        for j in object_fields(obj) {
+            if let Some(c) = object_index(&objects, *obj.add(j)) {
+                *obj.add(j) = forward[c] + 1;
+            }
+        }
+    }
+
+    // slide live objects down
+    for k in 0..objects.len() {
+        if marked[k] {
+            let obj = objects[k] as *const u64;
+            std::ptr::copy(obj, forward[k] as *mut u64, object_size(obj));
+        }
+    }
+
+    free
+}
+
+unsafe fn string_bytes<'a>(addr: *const u64) -> &'a [u8] {
+    std::slice::from_raw_parts(addr.add(1) as *const u8, (*addr >> 8) as usize)
+}
+
+// writes a string object holding bytes at dest and returns it as a snek value
+unsafe fn write_string(dest: *mut u64, bytes: &[u8]) -> i64 {
+    let words = (bytes.len() + 7) / 8;
+    *dest = ((bytes.len() as u64) << 8) | KIND_STRING;
+    for j in 1..=words {
+        *dest.add(j) = 0;
+    }
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), dest.add(1) as *mut u8, bytes.len());
+    dest as i64 + 1
+}
+
+// The compiled code has already made room at dest for the results.
+#[export_name = "\x01snek_string_append"]
+pub unsafe extern "C" fn snek_string_append(a: i64, b: i64, dest: *mut u64) -> i64 {
+    let mut bytes = string_bytes((a - 1) as *const u64).to_vec();
+    bytes.extend_from_slice(string_bytes((b - 1) as *const u64));
+    write_string(dest, &bytes)
+}
+
+// The byte at index idx (from 1, already checked to be in range) as a string
+// of its own; like string-length, this counts bytes and not characters.
+#[export_name = "\x01snek_string_ref"]
+pub unsafe extern "C" fn snek_string_ref(s: i64, idx: i64, dest: *mut u64) -> i64 {
+    let bytes = string_bytes((s - 1) as *const u64);
+    let k = (idx / 2 - 1) as usize;
+    write_string(dest, &bytes[k..k + 1])
+}
+
+// An integer outside the fixnum range, as a sign and a magnitude in
+// little-endian 64-bit limbs without leading zeros. Bignums only exist in
+// programs compiled with --bignum.
+struct Big {
+    neg: bool,
+    mag: Vec<u64>,
+}
+
+#[repr(C)]
+pub struct BigResult {
+    value: i64,
+    heap: *mut u64,
+}
+
+fn is_bignum(v: i64) -> bool {
+    v & 3 == 1 && v != 1 && unsafe { object_kind((v - 1) as *const u64) } == KIND_BIGNUM
+}
+
+fn trim(mag: &mut Vec<u64>) {
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+}
+
+// a fixnum or a bignum
+fn is_integer(v: i64) -> bool {
+    v & 1 == 0 || is_bignum(v)
+}
+
+// v must be an integer
+fn to_big(v: i64) -> Big {
+    if v & 1 == 0 {
+        let n = v >> 1;
+        let mag = if n == 0 { Vec::new() } else { vec![n.unsigned_abs()] };
+        Big { neg: n < 0, mag: mag }
+    } else {
+        let addr = (v - 1) as *const u64;
+        unsafe {
+            let len = (*addr >> 8) as usize;
+            let mag = (0..len).map(|j| *addr.add(2 + j)).collect();
+            Big { neg: *addr.add(1) != 0, mag: mag }
+        }
+    }
+}
+
+fn cmp_mag(a: &[u64], b: &[u64]) -> Ordering {
+    if a.len() != b.len() {
+        return a.len().cmp(&b.len());
+    }
+    for j in (0..a.len()).rev() {
+        if a[j] != b[j] {
+            return a[j].cmp(&b[j]);
+        }
+    }
+    Ordering::Equal
+}
+
+fn add_mag(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let mut out = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u128;
+    for j in 0..a.len().max(b.len()) {
+        let sum = *a.get(j).unwrap_or(&0) as u128 + *b.get(j).unwrap_or(&0) as u128 + carry;
+        out.push(sum as u64);
+        carry = sum >> 64;
+    }
+    out.push(carry as u64);
+    trim(&mut out);
+    out
+}
+
+// a - b, for a >= b
+fn sub_mag(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0u64;
+    for j in 0..a.len() {
+        let (d1, o1) = a[j].overflowing_sub(*b.get(j).unwrap_or(&0));
+        let (d2, o2) = d1.overflowing_sub(borrow);
+        out.push(d2);
+        borrow = (o1 || o2) as u64;
+    }
+    trim(&mut out);
+    out
+}
+
+fn mul_mag(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let mut out = vec![0u64; a.len() + b.len()];
+    for i in 0..a.len() {
+        let mut carry = 0u128;
+        for j in 0..b.len() {
+            let cur = out[i + j] as u128 + a[i] as u128 * b[j] as u128 + carry;
+            out[i + j] = cur as u64;
+            carry = cur >> 64;
+        }
+        out[i + b.len()] = carry as u64;
+    }
+    trim(&mut out);
+    out
+}
+
+// quotient and remainder of a / b (b not zero), one bit at a time
+fn divmod_mag(a: &[u64], b: &[u64]) -> (Vec<u64>, Vec<u64>) {
+    let mut q = vec![0u64; a.len()];
+    let mut r: Vec<u64> = Vec::new();
+    for bit in (0..a.len() * 64).rev() {
+        // r = r * 2 + next bit of a
+        let mut carry = (a[bit / 64] >> (bit % 64)) & 1;
+        for limb in r.iter_mut() {
+            let top = *limb >> 63;
+            *limb = (*limb << 1) | carry;
+            carry = top;
+        }
+        if carry != 0 {
+            r.push(carry);
+        }
+        if cmp_mag(&r, b) != Ordering::Less {
+            r = sub_mag(&r, b);
+            q[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+    trim(&mut q);
+    (q, r)
+}
+
+fn big_add(a: Big, b: Big) -> Big {
+    if a.neg == b.neg {
+        return Big { neg: a.neg, mag: add_mag(&a.mag, &b.mag) };
+    }
+    match cmp_mag(&a.mag, &b.mag) {
+        Ordering::Less => Big { neg: b.neg, mag: sub_mag(&b.mag, &a.mag) },
+        _ => Big { neg: a.neg, mag: sub_mag(&a.mag, &b.mag) },
+    }
+}
+
+fn big_cmp(a: &Big, b: &Big) -> Ordering {
+    match (a.neg, b.neg) {
+        (false, true) => Ordering::Greater,
+        (true, false) => Ordering::Less,
+        (false, false) => cmp_mag(&a.mag, &b.mag),
+        (true, true) => cmp_mag(&b.mag, &a.mag),
+    }
+}
+
+fn big_to_string(b: &Big) -> String {
+    const CHUNK: u128 = 1_000_000_000_000_000_000;
+    let mut mag = b.mag.clone();
+    let mut chunks = Vec::new();
+    while !mag.is_empty() {
+        let mut rem = 0u128;
+        for j in (0..mag.len()).rev() {
+            let cur = (rem << 64) | mag[j] as u128;
+            mag[j] = (cur / CHUNK) as u64;
+            rem = cur % CHUNK;
+        }
+        trim(&mut mag);
+        chunks.push(rem as u64);
+    }
+    let mut s = String::from(if b.neg { "-" } else { "" });
+    s.push_str(&chunks.last().unwrap_or(&0).to_string());
+    for c in chunks.iter().rev().skip(1) {
+        s.push_str(&format!("{:018}", c));
+    }
+    s
+}
+
+fn is_float(v: i64) -> bool {
+    v & 3 == 1 && v != 1 && unsafe { object_kind((v - 1) as *const u64) } == KIND_FLOAT
+}
+
+fn to_float(v: i64) -> f64 {
+    unsafe { f64::from_bits(*((v - 1) as *const u64).add(1)) }
+}
+
+// room for `words` words at *heap, collecting garbage first if the heap is
+// full; out of memory (error 5) if they still do not fit
+unsafe fn alloc(words: usize, heap: &mut *mut u64, stack_top: *mut u64, stack_base: *const u64) -> Result<*mut u64, i64> {
+    if (*heap).add(words) > HEAP_END {
+        *heap = snek_gc(*heap, stack_top, stack_base);
+        HEAP_PTR = *heap;
+        if (*heap).add(words) > HEAP_END {
+            return Err(5);
+        }
+    }
+    let dest = *heap;
+    *heap = dest.add(words);
+    Ok(dest)
+}
+
+unsafe fn from_float(f: f64, heap: &mut *mut u64, stack_top: *mut u64, stack_base: *const u64) -> Result<i64, i64> {
+    let dest = alloc(2, heap, stack_top, stack_base)?;
+    *dest = (1 << 8) | KIND_FLOAT;
+    *dest.add(1) = f.to_bits();
+    Ok(dest as i64 + 1)
+}
+
+// turns b back into a snek value: a fixnum when it fits, otherwise a new
+// bignum object
+unsafe fn from_big(b: Big, heap: &mut *mut u64, stack_top: *mut u64, stack_base: *const u64) -> Result<i64, i64> {
+    if b.mag.is_empty() {
+        return Ok(0);
+    }
+    if b.mag.len() == 1 && b.mag[0] <= 1 << 62 {
+        let n = if b.neg { -(b.mag[0] as i128) } else { b.mag[0] as i128 };
+        if n < 1 << 62 {
+            return Ok((n as i64) << 1);
+        }
+    }
+    let dest = alloc(b.mag.len() + 2, heap, stack_top, stack_base)?;
+    *dest = ((b.mag.len() as u64) << 8) | KIND_BIGNUM;
+    *dest.add(1) = b.neg as u64;
+    for (j, limb) in b.mag.iter().enumerate() {
+        *dest.add(2 + j) = *limb;
+    }
+    Ok(dest as i64 + 1)
+}
+
+// Slow path of the numeric operators, taken when an operand is not a fixnum
+// or, in bignum mode, when the fixnum operation overflowed. Floats only mix
+// with floats. `op` is one of the NUM_* codes of the compiler.
+#[export_name = "\x01snek_num_op"]
+pub unsafe extern "C" fn snek_num_op(op: i64, a: i64, b: i64, heap: *mut u64, stack_top: *mut u64, stack_base: *const u64) -> BigResult {
+    HEAP_PTR = heap;
+    // raised only once num_op has returned and dropped its bignums
+    match num_op(op, a, b, heap, stack_top, stack_base) {
+        Ok(res) => res,
+        Err(errcode) => {
+            raise_error(errcode, 0);
+            unreachable!()
+        },
+    }
+}
+
+// snek_num_op, with its errors as the error code
+unsafe fn num_op(op: i64, a: i64, b: i64, heap: *mut u64, stack_top: *mut u64, stack_base: *const u64) -> Result<BigResult, i64> {
+    let mut heap = heap;
+    if op == 10 {
+        // int->float
+        if !is_integer(a) {
+            return Err(1);
+        }
+        let x = to_big(a);
+        let f = x.mag.iter().rev().fold(0.0, |acc, limb| acc * 18446744073709551616.0 + *limb as f64);
+        return Ok(BigResult { value: from_float(if x.neg { -f } else { f }, &mut heap, stack_top, stack_base)?, heap: heap });
+    }
+    if op == 11 {
+        // float->int truncates toward zero
+        if !is_float(a) || to_float(a).is_nan() {
+            return Err(1);
+        }
+        let f = to_float(a).trunc();
+        if f < -4611686018427387904.0 || f >= 4611686018427387904.0 {
+            return Err(2);
+        }
+        return Ok(BigResult { value: (f as i64) << 1, heap: heap });
+    }
+    if is_float(a) || is_float(b) {
+        if !is_float(a) || !is_float(b) {
+            return Err(1);
+        }
+        let (x, y) = (to_float(a), to_float(b));
+        let value = match op {
+            0 => from_float(x + y, &mut heap, stack_top, stack_base)?,
+            1 => from_float(x - y, &mut heap, stack_top, stack_base)?,
+            2 => from_float(x * y, &mut heap, stack_top, stack_base)?,
+            3 | 4 => {
+                if y == 0.0 {
+                    return Err(7);
+                }
+                from_float(if op == 3 { x / y } else { x % y }, &mut heap, stack_top, stack_base)?
+            },
+            _ => {
+                let res = match op {
+                    5 => x < y,
+                    6 => x > y,
+                    7 => x >= y,
+                    8 => x <= y,
+                    _ => x == y,
+                };
+                if res { 7 } else { 3 }
+            },
+        };
+        return Ok(BigResult { value: value, heap: heap });
+    }
+    if op == 9 {
+        // = compares numbers by value and everything else by reference
+        let (num_a, num_b) = (is_integer(a), is_integer(b));
+        if num_a != num_b || (!num_a && (a & 2) != (b & 2)) {
+            return Err(1);
+        }
+        let eq = if num_a { big_cmp(&to_big(a), &to_big(b)) == Ordering::Equal } else { a == b };
+        return Ok(BigResult { value: if eq { 7 } else { 3 }, heap: heap });
+    }
+    if !is_integer(a) || !is_integer(b) {
+        return Err(1);
+    }
+    let (x, y) = (to_big(a), to_big(b));
+    let value = match op {
+        0 => from_big(big_add(x, y), &mut heap, stack_top, stack_base)?,
+        1 => from_big(big_add(x, Big { neg: !y.neg, mag: y.mag }), &mut heap, stack_top, stack_base)?,
+        2 => from_big(Big { neg: x.neg != y.neg, mag: mul_mag(&x.mag, &y.mag) }, &mut heap, stack_top, stack_base)?,
+        3 | 4 => {
+            if y.mag.is_empty() {
+                return Err(7);
+            }
+            // truncating division, the remainder takes the sign of x
+            let (q, r) = divmod_mag(&x.mag, &y.mag);
+            let res = if op == 3 { Big { neg: x.neg != y.neg, mag: q } } else { Big { neg: x.neg, mag: r } };
+            from_big(res, &mut heap, stack_top, stack_base)?
+        },
+        _ => {
+            let ord = big_cmp(&x, &y);
+            let res = match op {
+                5 => ord == Ordering::Less,
+                6 => ord == Ordering::Greater,
+                7 => ord != Ordering::Less,
+                _ => ord != Ordering::Greater,
+            };
+            if res { 7 } else { 3 }
+        },
+    };
+    Ok(BigResult { value: value, heap: heap })
+}
+
+// as in the compiler's reader: a number with a decimal point or an exponent,
+// so that inf and nan are not floats and too big integers stay an error
+fn is_float_literal(input: &str) -> bool {
+    input.trim_start_matches('-').starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        && input.contains(|c: char| c == '.' || c == 'e' || c == 'E')
+        && input.parse::<f64>().is_ok()
+}
+
+// a float input is boxed at the start of the heap, moving *heap past it
+fn parse_input(input: &str, heap: &mut *mut u64) -> i64 {
     // TODO: parse the input string into internal value representation
     // 0
     if input == "nil" {
@@ -38,6 +581,16 @@ fn parse_input(input: &str) -> i64 {
     else if input == "true" {
         7
     }
+    else if is_float_literal(input) {
+        let f = input.parse::<f64>().unwrap();
+        unsafe {
+            let dest = *heap;
+            *dest = (1 << 8) | KIND_FLOAT;
+            *dest.add(1) = f.to_bits();
+            *heap = dest.add(2);
+            dest as i64 + 1
+        }
+    }
     else {
         let num = match input.parse::<i64>() {
             Ok(n) => n,
@@ -52,42 +605,139 @@ fn parse_input(input: &str) -> i64 {
 
 #[export_name = "\x01snek_print"]
 fn print_value(i:i64) {
-    sn_print(i);
-    println!();
+    println!("{}", value_to_string(i));
 }
 
-fn sn_print(i:i64) {
+fn value_to_string(i: i64) -> String {
+    let mut out = String::new();
+    sn_print(i, &mut HashSet::new(), &mut out);
+    out
+}
+
+// Appends the printed form of i to out. `seen` holds the tuples currently
+// being printed, so a tuple that contains itself is printed as a marker
+// instead of looping forever.
+fn sn_print(i:i64, seen: &mut HashSet<i64>, out: &mut String) {
     if i % 2 == 0 {
-        print!("{}", i / 2);
+        out.push_str(&format!("{}", i / 2));
     } else if i == 7 {
-        print!("true");
+        out.push_str("true");
     } else if i == 3 {
-        print!("false");
+        out.push_str("false");
     } else if i == 1 {
-        print!("nil");
-    } else if i & 3 == 1 {
-        print!{"(tuple"};
+        out.push_str("nil");
+    } else if i & 3 == 1 && unsafe { object_kind((i - 1) as *const u64) } == KIND_CLOSURE {
+        out.push_str("<function>");
+    } else if is_bignum(i) {
+        out.push_str(&format!("{}", big_to_string(&to_big(i))));
+    } else if is_float(i) {
+        out.push_str(&format!("{:?}", to_float(i)));
+    } else if i & 3 == 1 && unsafe { object_kind((i - 1) as *const u64) } == KIND_STRING {
+        // quoted when nested inside a tuple, raw at the top level
+        let st = String::from_utf8_lossy(unsafe { string_bytes((i - 1) as *const u64) });
+        if seen.is_empty() {
+            out.push_str(&format!("{}", st));
+        } else {
+            out.push_str(&format!("{:?}", st));
+        }
+    } else if i & 3 == 1 && seen.contains(&i) {
+        out.push_str("<cyclic tuple>");
+    } else if i & 3 == 1 && unsafe { object_kind((i - 1) as *const u64) } == KIND_STRUCT {
+        // (name field: value ...)
+        seen.insert(i);
         let addr: *const u64 = (i - 1) as *const u64;
-        let len_tp = unsafe{ *addr };
+        let structs = unsafe { &*std::ptr::addr_of!(STRUCTS) };
+        let names = &structs[unsafe { *addr >> 32 } as usize];
+        out.push_str(&format!("({}", names[0]));
+        for (j, field) in names[1..].iter().enumerate() {
+            out.push_str(&format!(" {}: ", field));
+            sn_print(unsafe { *addr.add(j + 1) } as i64, seen, out);
+        }
+        out.push_str(")");
+        seen.remove(&i);
+    } else if i & 3 == 1 && unsafe { object_kind((i - 1) as *const u64) } == KIND_TUPLE {
+        seen.insert(i);
+        out.push_str("(tuple");
+        let addr: *const u64 = (i - 1) as *const u64;
+        let len_tp = unsafe{ *addr >> 8 };
         // println!("{}", i - 1);
         // println!("{}", len_tp);
         for j in 1..=len_tp {
-            print!(" ");
-            sn_print(unsafe{ *addr.offset(j as isize)} as i64);
+            out.push_str(" ");
+            sn_print(unsafe{ *addr.offset(j as isize)} as i64, seen, out);
         }
-        print!{")"}
+        out.push_str(")");
+        seen.remove(&i);
     } else {
-        println!("Unknown:{}", i);
+        out.push_str(&format!("Unknown:{}", i));
+    }
+}
+
+#[export_name = "\x01snek_struct_info"]
+pub unsafe extern "C" fn snek_struct_info(id: i64, desc: i64) -> i64 {
+    let desc = String::from_utf8_lossy(string_bytes((desc - 1) as *const u64)).to_string();
+    let structs = &mut *std::ptr::addr_of_mut!(STRUCTS);
+    if structs.len() <= id as usize {
+        structs.resize(id as usize + 1, Vec::new());
+    }
+    structs[id as usize] = desc.split(' ').map(|w| w.to_string()).collect();
+    1
+}
+
+#[export_name = "\x01snek_equal"]
+pub extern "C" fn snek_equal(a: i64, b: i64) -> i64 {
+    let mut assumed = HashSet::new();
+    if sn_equal(a, b, &mut assumed) { 7 } else { 3 }
+}
+
+// Structural equality. Pairs of objects already being compared are assumed
+// equal, so comparing cyclic values terminates.
+fn sn_equal(a: i64, b: i64, assumed: &mut HashSet<(i64, i64)>) -> bool {
+    if a == b {
+        return true;
+    }
+    if a & 3 != 1 || b & 3 != 1 || a == 1 || b == 1 {
+        return false;
+    }
+    if !assumed.insert((a, b)) {
+        return true;
+    }
+    let addr_a: *const u64 = (a - 1) as *const u64;
+    let addr_b: *const u64 = (b - 1) as *const u64;
+    // same kind and size
+    if unsafe { *addr_a != *addr_b } {
+        return false;
+    }
+    // words that are not snek values (e.g. code pointers) must be identical
+    let fields = unsafe { object_fields(addr_a) };
+    for j in 1..fields.start {
+        if unsafe { *addr_a.add(j) != *addr_b.add(j) } {
+            return false;
+        }
+    }
+    for j in fields {
+        let (x, y) = unsafe { (*addr_a.add(j), *addr_b.add(j)) };
+        if !sn_equal(x as i64, y as i64, assumed) {
+            return false;
+        }
     }
+    true
 }
 
-fn main() {
+// public so that the driver can prebuild the runtime as a library and link
+// programs with a main that calls this one
+pub fn main() {
     let args: Vec<String> = env::args().collect();
     let input = if args.len() == 2 { &args[1] } else { "false" };
-    let input = parse_input(&input);
-    let mut memory = Vec::<u64>::with_capacity(100000);
+    let mut memory = Vec::<u64>::with_capacity(HEAP_SIZE);
     let buffer: *mut u64 = memory.as_mut_ptr();
     // println!("{}", buffer as u64);
-    let i: i64 = unsafe { our_code_starts_here(input, buffer) };
+    let mut heap = buffer;
+    let input = parse_input(&input, &mut heap);
+    let i: i64 = unsafe {
+        HEAP_START = buffer;
+        HEAP_END = buffer.add(HEAP_SIZE);
+        our_code_starts_here(input, heap, HEAP_END, std::ptr::addr_of_mut!(HANDLER))
+    };
     print_value(i);
 }
@@ -0,0 +1,172 @@
+// Builds executables the way the Makefile does: the compiled assembly is
+// assembled with nasm, archived with ar as libour_code.a, and linked with the
+// runtime. Instead of compiling runtime/start.rs for every program, the
+// runtime is built once into a library that is cached next to the compiler,
+// and each program is linked with a main that only calls the runtime's.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{compile, CompileError, Options};
+
+// the runtime the compiler was built with, used unless SNEK_RUNTIME is set
+const RUNTIME: &str = include_str!("../runtime/start.rs");
+
+// the crate compiled for each program
+const MAIN: &str = "extern crate snek_runtime;\n\nfn main() {\n    snek_runtime::main()\n}\n";
+
+// Why an executable could not be built.
+#[derive(Debug)]
+pub enum BuildError {
+  // the program was rejected by the compiler
+  Compile(Vec<CompileError>),
+  // the runtime source given in place of the bundled one does not exist
+  RuntimeNotFound(PathBuf),
+  // a tool could not be started, or a file could not be read or written
+  Io(String, std::io::Error),
+  // building the runtime, the assembler, archiver or linker failed; its
+  // command line, exit status (None when killed by a signal) and error output
+  Tool { step: &'static str, command: String, status: Option<i32>, stderr: String },
+}
+
+impl fmt::Display for BuildError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      BuildError::Compile(errors) => write!(f, "{} compile error(s)", errors.len()),
+      BuildError::RuntimeNotFound(path) => write!(f, "runtime not found at {}", path.display()),
+      BuildError::Io(what, e) => write!(f, "{}: {}", what, e),
+      BuildError::Tool { step, command, status, stderr } => {
+        match status {
+          Some(code) => write!(f, "{} failed with status {}: {}", step, code, command)?,
+          None => write!(f, "{} was killed: {}", step, command)?,
+        }
+        if !stderr.trim().is_empty() {
+          write!(f, "\n{}", stderr.trim_end())?;
+        }
+        Ok(())
+      },
+    }
+  }
+}
+
+impl std::error::Error for BuildError {}
+
+// The runtime source to link with in place of the bundled one: $SNEK_RUNTIME,
+// when set.
+pub fn runtime_path() -> Option<PathBuf> {
+  std::env::var_os("SNEK_RUNTIME").map(PathBuf::from)
+}
+
+// Where prebuilt runtimes are kept: next to the compiler's executable, or in
+// the temporary directory when that is unknown.
+fn cache_dir() -> PathBuf {
+  match std::env::current_exe() {
+    Ok(exe) => exe.with_file_name("snek-runtime"),
+    Err(_) => std::env::temp_dir().join("snek-runtime"),
+  }
+}
+
+fn target() -> Option<&'static str> {
+  if cfg!(target_os = "macos") { Some("x86_64-apple-darwin") } else { None }
+}
+
+// A name no other build, in this process or another, uses at the same time.
+fn unique(prefix: &str) -> String {
+  static N: AtomicUsize = AtomicUsize::new(0);
+  format!("{}-{}-{}", prefix, std::process::id(), N.fetch_add(1, Ordering::Relaxed))
+}
+
+fn run_tool(step: &'static str, cmd: &mut Command) -> Result<(), BuildError> {
+  let command = format!("{:?}", cmd).replace('"', "");
+  let output = cmd.output().map_err(|e| BuildError::Io(format!("cannot run {}", command), e))?;
+  if !output.status.success() {
+    return Err(BuildError::Tool { step, command, status: output.status.code(), stderr: String::from_utf8_lossy(&output.stderr).into_owned() });
+  }
+  Ok(())
+}
+
+// The version of rustc, as `rustc -vV` prints it. Libraries built by another
+// rustc cannot be linked, so it is part of what a prebuilt runtime is kept for.
+fn rustc_version() -> Result<String, BuildError> {
+  let output = Command::new("rustc").arg("-vV").output().map_err(|e| BuildError::Io("cannot run rustc -vV".to_string(), e))?;
+  if !output.status.success() {
+    return Err(BuildError::Tool { step: "rustc version", command: "rustc -vV".to_string(), status: output.status.code(), stderr: String::from_utf8_lossy(&output.stderr).into_owned() });
+  }
+  Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// The runtime with the source `src` compiled into a library, building it
+// unless the cache already holds one for the same source and rustc. It is
+// built under a temporary name and then renamed, so that concurrent builds
+// never see half of it.
+fn prebuilt_runtime(src: &str) -> Result<PathBuf, BuildError> {
+  let mut hasher = DefaultHasher::new();
+  src.hash(&mut hasher);
+  target().hash(&mut hasher);
+  rustc_version()?.hash(&mut hasher);
+  let dir = cache_dir();
+  let lib = dir.join(format!("libsnek_runtime-{:016x}.rlib", hasher.finish()));
+  if lib.is_file() {
+    return Ok(lib);
+  }
+  fs::create_dir_all(&dir).map_err(|e| BuildError::Io(format!("cannot create {}", dir.display()), e))?;
+  let name = unique("runtime");
+  let rs = dir.join(format!("{}.rs", name));
+  let tmp = dir.join(format!("{}.rlib", name));
+  fs::write(&rs, src).map_err(|e| BuildError::Io(format!("cannot write {}", rs.display()), e))?;
+  let mut rustc = Command::new("rustc");
+  if let Some(target) = target() {
+    rustc.arg("--target").arg(target);
+  }
+  rustc.args(["--crate-type", "rlib", "--crate-name", "snek_runtime", "--cap-lints", "allow"]).arg(&rs).arg("-o").arg(&tmp);
+  let result = run_tool("runtime build", &mut rustc).and_then(|()| {
+    fs::rename(&tmp, &lib).map_err(|e| BuildError::Io(format!("cannot write {}", lib.display()), e))
+  });
+  let _ = fs::remove_file(&rs);
+  let _ = fs::remove_file(&tmp);
+  result.map(|()| lib)
+}
+
+// Compiles the program `src` and links it into the executable `out` with the
+// runtime: the one bundled with the compiler, or the source at `runtime`.
+pub fn build(src: &str, opts: Options, runtime: Option<&Path>, out: &Path) -> Result<(), BuildError> {
+  let asm = compile(src, opts).map_err(BuildError::Compile)?;
+  let lib = match runtime {
+    None => prebuilt_runtime(RUNTIME)?,
+    Some(path) if path.is_file() => {
+      let runtime_src = fs::read_to_string(path).map_err(|e| BuildError::Io(format!("cannot read {}", path.display()), e))?;
+      prebuilt_runtime(&runtime_src)?
+    },
+    Some(path) => return Err(BuildError::RuntimeNotFound(path.to_path_buf())),
+  };
+  let dir = std::env::temp_dir().join(unique("snek"));
+  fs::create_dir_all(&dir).map_err(|e| BuildError::Io(format!("cannot create {}", dir.display()), e))?;
+  let result = link(&asm, &lib, out, &dir);
+  let _ = fs::remove_dir_all(&dir);
+  result
+}
+
+fn link(asm: &str, lib: &Path, out: &Path, dir: &Path) -> Result<(), BuildError> {
+  let format = if cfg!(target_os = "macos") { "macho64" } else { "elf64" };
+  let s = dir.join("our_code.s");
+  let o = dir.join("our_code.o");
+  let a = dir.join("libour_code.a");
+  let main = dir.join("main.rs");
+  fs::write(&s, asm).map_err(|e| BuildError::Io(format!("cannot write {}", s.display()), e))?;
+  fs::write(&main, MAIN).map_err(|e| BuildError::Io(format!("cannot write {}", main.display()), e))?;
+  run_tool("assembler", Command::new("nasm").arg("-f").arg(format).arg(&s).arg("-o").arg(&o))?;
+  run_tool("archiver", Command::new("ar").arg("rcs").arg(&a).arg(&o))?;
+  let mut rustc = Command::new("rustc");
+  if let Some(target) = target() {
+    rustc.arg("--target").arg(target);
+  }
+  let mut extern_arg = std::ffi::OsString::from("snek_runtime=");
+  extern_arg.push(lib);
+  rustc.arg("--extern").arg(extern_arg).arg("-L").arg(dir).arg(&main).arg("-o").arg(out);
+  run_tool("linker", &mut rustc)
+}
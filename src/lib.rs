@@ -0,0 +1,3045 @@
+use std::fmt;
+
+use sexp::Atom;
+use sexp::Atom::*;
+
+use im::HashMap;
+
+pub mod driver;
+
+#[derive(Debug)]
+enum Val {
+  Reg(Reg),
+  Imm(i64),
+  RegOnset(Reg, i64),
+  RegOffset(Reg, i64),
+  RegSet(Reg),
+}
+
+#[derive(Debug, Clone)]
+enum Label {
+  TYPEERROR,
+  OVERFLOW,
+  OUTBOUNDERROR,
+  NILREF,
+  OUTOFMEMORY,
+  ARITYERROR,
+  DIVZERO,
+  NOMATCH,
+  TUPLEARITY,
+  LName(String),
+  // an error label reached through a stub that first moves rsp down by the
+  // given number of bytes, below the live temporaries
+  Below(Box<Label>, i64),
+}
+
+#[derive(Debug)]
+enum Reg {
+  RAX,
+  RBX,
+  RSP,
+  RDI,
+  RSI,
+  RCX,
+  RDX,
+  R8,
+  R9,
+  RTWELVE,
+  RTHIRTEEN,
+  RFOURTEEN,
+  RFIFTHTEEN,
+}
+
+#[derive(Debug)]
+enum Instr {
+  IMov(Val, Val),
+  IAdd(Val, Val),
+  ISub(Val, Val),
+  IMul(Val, Val),
+  IDiv(Val),
+  Cqo,
+  Test(Val, Val),
+  Cmp(Val, Val),
+  Sal(Val, Val),
+  Sar(Val, Val),
+  And(Val, Val),
+  Or(Val, Val),
+  Xor(Val, Val),
+  Push(Val),
+  Pop(Val),
+  Jmp(Label),
+  Je(Label),
+  Jne(Label),
+  Jg(Label),
+  Jl(Label),
+  Jge(Label),
+  Jle(Label),
+  Jo(Label),
+  Nothing(Label),
+  Call(Label),
+  CallInd(Val),
+  JmpInd(Val),
+  Lea(Val, Label),
+  Ret,
+}
+
+#[derive(Debug, Clone)]
+enum Op1 {
+  Add1,
+  Sub1,
+  IsNum,
+  IsBool,
+  StrLen,
+  Not,
+  Len,
+  IsTuple,
+  IsNil,
+  TypeOf,
+  IsFloat,
+  IntToFloat,
+  FloatToInt,
+  // header of the struct type (and index of the field)
+  IsStruct(i64),
+  GetField(i64, usize),
+  // first and last struct id of the variants of a data type
+  IsData(i64, i64),
+  // registers the name and field names of struct type `id` with the runtime
+  StructInfo(usize),
+  Raise,
+}
+
+#[derive(Debug, Clone)]
+enum Op2 {
+  Plus,
+  Minus,
+  Times,
+  Divide,
+  Mod,
+  Lt,
+  Gt,
+  Ge,
+  Le,
+  Eq,
+  Equal,
+  StrAppend,
+  StrRef,
+  MakeVec,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+  Number(i64),
+  Float(f64),
+  Str(String),
+  NIL,
+  TRUE,
+  FALSE,
+  INPUT,
+  Id(String),
+  Let(Vec<(Pattern, Expr)>, Box<Expr>),
+  UnOp(Op1, Box<Expr>),
+  BinOp(Op2, Box<Expr>, Box<Expr>),
+  Set(String, Box<Expr>),
+  If(Box<Expr>, Box<Expr>, Box<Expr>),
+  Block(Vec<Expr>),
+  And(Vec<Expr>),
+  Or(Vec<Expr>),
+  Loop(Box<Expr>),
+  Break(Box<Expr>),
+  Tuple(Vec<Expr>),
+  // an object of struct type id, built by the generated constructors
+  Struct(usize, Vec<Expr>),
+  Index(Box<Expr>, Box<Expr>),
+  SetIndex(Box<Expr>, Box<Expr>, Box<Expr>),
+  Funccall(String, Vec<Expr>),
+  Lambda(Vec<String>, Box<Expr>),
+  Call(Box<Expr>, Vec<Expr>),
+  Match(Box<Expr>, Vec<(Pattern, Expr)>),
+  // (try body (catch x handler))
+  Try(Box<Expr>, String, Box<Expr>),
+  Letrec(Vec<(String, Vec<String>, Expr)>, Box<Expr>),
+  At(Span, Box<Expr>), // where in the source the expression was parsed from
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+  Num(i64),
+  Bool(bool),
+  Nil,
+  Wildcard,
+  Var(String),
+  Tuple(Vec<Pattern>),
+  // the header of the struct type and the patterns of its fields
+  Struct(i64, Vec<Pattern>),
+}
+
+// A constructor of a struct type, so that patterns like (circle r) can be
+// checked and compiled: the header of its objects and their number of fields.
+#[derive(Debug, Clone)]
+struct Constructor {
+  name: String,
+  header: i64,
+  size: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Statement{
+  Definition(Vec<String>, Box<Expr>, Span),
+  Expression(Box<Expr>, Span),
+}
+
+// Why a program was rejected. Every phase returns these instead of panicking;
+// the compiler binary prints them with render_error and exits with status 2.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+  Syntax(String),
+  UnboundVariable(String),
+  UnboundFunction(String),
+  DuplicateBinding(String),
+  DuplicateFunction(String),
+  ArityMismatch(String, usize, usize),
+  ReservedKeyword(String),
+  BreakOutsideLoop,
+  AssignCaptured(String),
+  LiteralOutOfRange(String),
+  InputInFunction,
+  UnknownConstructor(String),
+  UnknownFlag(String),
+  // a program that the earlier phases should not have produced
+  Internal(String),
+  At(Span, Box<CompileError>),
+}
+
+impl CompileError {
+  // locates the error at span, unless it already has a more precise location
+  fn at(self, span: Span) -> CompileError {
+    match self {
+      CompileError::At(..) => self,
+      _ => CompileError::At(span, Box::new(self)),
+    }
+  }
+}
+
+fn syntax(s: &Sexp) -> CompileError {
+  CompileError::Syntax(s.to_string()).at(s.span())
+}
+
+impl fmt::Display for CompileError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CompileError::Syntax(s) => write!(f, "invalid syntax: {}", s),
+      CompileError::UnboundVariable(x) => write!(f, "unbound variable identifier {}", x),
+      CompileError::UnboundFunction(x) => write!(f, "no such function {}", x),
+      CompileError::DuplicateBinding(x) => write!(f, "duplicate binding {}", x),
+      CompileError::DuplicateFunction(x) => write!(f, "multiple functions named {}", x),
+      CompileError::ArityMismatch(x, expected, found) => write!(f, "wrong number of arguments to {}: expected {}, found {}", x, expected, found),
+      CompileError::ReservedKeyword(x) => write!(f, "keyword {} used as a name", x),
+      CompileError::BreakOutsideLoop => write!(f, "break outside of a loop"),
+      CompileError::AssignCaptured(x) => write!(f, "cannot assign captured variable {}", x),
+      CompileError::LiteralOutOfRange(n) => write!(f, "literal out of range: {}", n),
+      CompileError::InputInFunction => write!(f, "input used inside a function"),
+      CompileError::UnknownConstructor(x) => write!(f, "unknown constructor {}", x),
+      CompileError::Internal(x) => write!(f, "internal compiler error: {}", x),
+      CompileError::UnknownFlag(x) => write!(f, "unknown flag {}", x),
+      CompileError::At(_, e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl std::error::Error for CompileError {}
+
+// names that let, set!, functions and parameters cannot bind: the special
+// forms and the built-in operations
+const KEYWORDS: [&str; 43] = [
+  "let", "add1", "sub1", "true", "false", "set!", "loop", "break", "if", "block", "input", "nil",
+  "tuple", "index", "setindex!", "fun", "lambda", "letrec", "match", "try", "catch", "raise",
+  "and", "or", "not", "struct", "data", "make-vec", "equal", "mod", "print", "len", "typeof",
+  "isnum", "isbool", "istuple", "isnil", "isfloat", "int->float", "float->int", "string-append",
+  "string-ref", "string-length",
+];
+
+fn check_name(x: &str) -> Result<(), CompileError> {
+  if KEYWORDS.contains(&x) {
+    return Err(CompileError::ReservedKeyword(x.to_string()));
+  }
+  Ok(())
+}
+
+// Byte range of the source a form was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+}
+
+// An s-expression as read by read_sexp, with the span it was read from.
+#[derive(Debug, Clone)]
+enum Sexp {
+  Atom(Atom, Span),
+  List(Vec<Sexp>, Span),
+}
+
+impl Sexp {
+  fn span(&self) -> Span {
+    match self {
+      Sexp::Atom(_, span) | Sexp::List(_, span) => *span,
+    }
+  }
+}
+
+impl fmt::Display for Sexp {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Sexp::Atom(a, _) => write!(f, "{}", a),
+      Sexp::List(xs, _) => {
+        write!(f, "(")?;
+        for (i, x) in xs.iter().enumerate() {
+          write!(f, "{}{}", if i == 0 { "" } else { " " }, x)?;
+        }
+        write!(f, ")")
+      },
+    }
+  }
+}
+
+fn skip_space(src: &[u8], pos: &mut usize) {
+  while *pos < src.len() && src[*pos].is_ascii_whitespace() {
+    *pos += 1;
+  }
+}
+
+// Reads one s-expression starting at pos. Unlike sexp::parse, string literals
+// keep their surrounding quotes (with escapes resolved) so that parse_expr can
+// tell "x" from the identifier x. Integers out of range are added to errors and
+// read as 0; unbalanced parentheses and strings stop the reading.
+fn read_sexp(src: &[u8], pos: &mut usize, errors: &mut Vec<CompileError>) -> Result<Sexp, CompileError> {
+  skip_space(src, pos);
+  let start = *pos;
+  if *pos >= src.len() {
+    return Err(CompileError::Syntax("unexpected end of input".to_string()).at(Span { start, end: start }));
+  }
+  match src[*pos] {
+    b'(' => {
+      *pos += 1;
+      let mut vec = Vec::new();
+      loop {
+        skip_space(src, pos);
+        if *pos >= src.len() {
+          return Err(CompileError::Syntax("unclosed parenthesis".to_string()).at(Span { start, end: start + 1 }));
+        }
+        if src[*pos] == b')' {
+          *pos += 1;
+          return Ok(Sexp::List(vec, Span { start, end: *pos }));
+        }
+        vec.push(read_sexp(src, pos, errors)?);
+      }
+    },
+    b')' => Err(CompileError::Syntax("unexpected )".to_string()).at(Span { start, end: start + 1 })),
+    b'"' => {
+      *pos += 1;
+      let mut st = vec![b'"'];
+      loop {
+        if *pos >= src.len() {
+          return Err(CompileError::Syntax("unclosed string literal".to_string()).at(Span { start, end: start + 1 }));
+        }
+        let c = src[*pos];
+        *pos += 1;
+        match c {
+          b'"' => break,
+          b'\\' if *pos < src.len() => {
+            match src[*pos] {
+              b'n' => st.push(b'\n'),
+              b't' => st.push(b'\t'),
+              other => st.push(other),
+            }
+            *pos += 1;
+          },
+          _ => st.push(c),
+        }
+      }
+      st.push(b'"');
+      Ok(Sexp::Atom(S(String::from_utf8_lossy(&st).into_owned()), Span { start, end: *pos }))
+    },
+    _ => {
+      while *pos < src.len() && !src[*pos].is_ascii_whitespace() && src[*pos] != b'(' && src[*pos] != b')' && src[*pos] != b'"' {
+        *pos += 1;
+      }
+      let span = Span { start, end: *pos };
+      let token = String::from_utf8_lossy(&src[start..*pos]).into_owned();
+      let digits = token.strip_prefix('-').unwrap_or(&token);
+      if let Ok(n) = token.parse::<i64>() {
+        Ok(Sexp::Atom(I(n), span))
+      } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        // an integer too big for i64, not a float
+        errors.push(CompileError::LiteralOutOfRange(token).at(span));
+        Ok(Sexp::Atom(I(0), span))
+      } else if token.trim_start_matches('-').starts_with(|c: char| c.is_ascii_digit() || c == '.') && token.parse::<f64>().is_ok() {
+        // only numeric-looking tokens, so that inf and nan stay identifiers
+        Ok(Sexp::Atom(F(token.parse::<f64>().unwrap()), span))
+      } else {
+        Ok(Sexp::Atom(S(token), span))
+      }
+    },
+  }
+}
+
+// Reads the whole program into one list of its top-level forms. A stray )
+// is reported and skipped, so that the errors of the whole file come at once.
+fn read_prog(src: &str) -> Result<Sexp, Vec<CompileError>> {
+  let bytes = src.as_bytes();
+  let mut pos = 0;
+  let mut vec = Vec::new();
+  let mut errors = Vec::new();
+  loop {
+    skip_space(bytes, &mut pos);
+    if pos >= bytes.len() {
+      break;
+    }
+    match read_sexp(bytes, &mut pos, &mut errors) {
+      Ok(s) => vec.push(s),
+      Err(e) => {
+        errors.push(e);
+        pos += 1;
+      },
+    }
+  }
+  if !errors.is_empty() {
+    return Err(errors);
+  }
+  Ok(Sexp::List(vec, Span { start: 0, end: bytes.len() }))
+}
+
+fn parse_bind(s: &Sexp, cs: &[Constructor]) -> Result<(Pattern, Expr), CompileError> {
+  Ok(match s {
+    Sexp::List(vec, _) => {
+      match &vec[..] {
+        [Sexp::Atom(S(name), _), e] => (Pattern::Var(name.to_string()), parse_expr(e, cs)?),
+        [Sexp::List(ps, _), e] => {
+          // (tuple x y ...) only binds names, no nested patterns
+          let names = match &ps[..] {
+            [Sexp::Atom(S(tuple), _), names @ ..] if tuple == "tuple" && !names.is_empty() => names,
+            _ => return Err(syntax(s)),
+          };
+          let mut vec = Vec::new();
+          for name in names {
+            match parse_pattern(name, cs)? {
+              p @ (Pattern::Var(_) | Pattern::Wildcard) => vec.push(p),
+              _ => return Err(syntax(s)),
+            }
+          }
+          (Pattern::Tuple(vec), parse_expr(e, cs)?)
+        },
+        _ => return Err(syntax(s)),
+      }
+    }
+    _ => return Err(syntax(s)),
+  })
+}
+
+fn parse_pattern(s: &Sexp, cs: &[Constructor]) -> Result<Pattern, CompileError> {
+  Ok(match s {
+    Sexp::Atom(I(n), _) => Pattern::Num(i64::try_from(*n).unwrap()),
+    Sexp::Atom(S(x), _) if x == "_" => Pattern::Wildcard,
+    Sexp::Atom(S(x), _) if x == "nil" => Pattern::Nil,
+    Sexp::Atom(S(x), _) if x == "true" => Pattern::Bool(true),
+    Sexp::Atom(S(x), _) if x == "false" => Pattern::Bool(false),
+    Sexp::Atom(S(x), _) if !x.starts_with('"') => Pattern::Var(x.to_string()),
+    Sexp::List(vec, _) => {
+      match &vec[..] {
+        [Sexp::Atom(S(tuple), _), ps @ ..] if tuple == "tuple" && !ps.is_empty() => Pattern::Tuple(ps.iter().map(|p| parse_pattern(p, cs)).collect::<Result<_, _>>()?),
+        [Sexp::Atom(S(name), _), ps @ ..] => {
+          let (header, size) = match cs.iter().find(|c| &c.name == name) {
+            Some(c) => (c.header, c.size),
+            None => return Err(CompileError::UnknownConstructor(name.to_string()).at(s.span())),
+          };
+          if ps.len() != size {
+            return Err(CompileError::ArityMismatch(name.to_string(), size, ps.len()).at(s.span()));
+          }
+          Pattern::Struct(header, ps.iter().map(|p| parse_pattern(p, cs)).collect::<Result<_, _>>()?)
+        },
+        _ => return Err(syntax(s)),
+      }
+    },
+    _ => return Err(syntax(s)),
+  })
+}
+
+fn parse_expr(s: &Sexp, cs: &[Constructor]) -> Result<Expr, CompileError> {
+  let e = match s {
+    Sexp::Atom(I(n), _) => Expr::Number(i64::try_from(*n).unwrap()),
+    Sexp::Atom(F(f), _) => Expr::Float(*f),
+    Sexp::Atom(S(st), _) if st.starts_with('"') => Expr::Str(st[1..st.len() - 1].to_string()),
+    Sexp::Atom(S(keyword), _) if keyword == "nil" => Expr::NIL,
+    Sexp::Atom(S(keyword), _) if keyword == "true" => Expr::TRUE,
+    Sexp::Atom(S(keyword), _) if keyword == "false" => Expr::FALSE,
+    Sexp::Atom(S(keyword), _) if keyword == "input" => Expr::INPUT,
+    Sexp::Atom(S(id), _) => Expr::Id(id.to_string()),
+    Sexp::List(vec, _) => {
+      match &vec[..] {
+        [Sexp::Atom(S(op), _), e] if op == "add1" => Expr::UnOp(Op1::Add1, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "sub1" => Expr::UnOp(Op1::Sub1, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "isnum" => Expr::UnOp(Op1::IsNum, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "isbool" => Expr::UnOp(Op1::IsBool, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "istuple" => Expr::UnOp(Op1::IsTuple, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "isnil" => Expr::UnOp(Op1::IsNil, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "len" => Expr::UnOp(Op1::Len, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "typeof" => Expr::UnOp(Op1::TypeOf, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "isfloat" => Expr::UnOp(Op1::IsFloat, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "int->float" => Expr::UnOp(Op1::IntToFloat, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "float->int" => Expr::UnOp(Op1::FloatToInt, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "raise" => Expr::UnOp(Op1::Raise, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "not" => Expr::UnOp(Op1::Not, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e] if op == "string-length" => Expr::UnOp(Op1::StrLen, Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "+" => Expr::BinOp(Op2::Plus, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "-" => Expr::BinOp(Op2::Minus, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "*" => Expr::BinOp(Op2::Times, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "/" => Expr::BinOp(Op2::Divide, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "mod" => Expr::BinOp(Op2::Mod, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "<" => Expr::BinOp(Op2::Lt, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == ">" => Expr::BinOp(Op2::Gt, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == ">=" => Expr::BinOp(Op2::Ge, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "<=" => Expr::BinOp(Op2::Le, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "=" => Expr::BinOp(Op2::Eq, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "equal" => Expr::BinOp(Op2::Equal, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "string-append" => Expr::BinOp(Op2::StrAppend, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "make-vec" => Expr::BinOp(Op2::MakeVec, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(op), _), e1, e2] if op == "string-ref" => Expr::BinOp(Op2::StrRef, Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(set), _), Sexp::Atom(S(name), _), e2] if set == "set!" => Expr::Set(name.to_string(), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(if_), _), e1, e2, e3] if if_ == "if" => Expr::If(Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?), Box::new(parse_expr(e3, cs)?)),
+        [Sexp::Atom(S(block), _), body @ ..] if block == "block" => {
+          if body.is_empty() {
+            return Err(syntax(s));
+          }
+          let mut vec = Vec::new();
+          for e in body {
+            vec.push(parse_expr(e, cs)?);
+          }
+          Expr::Block(vec)
+        },
+        [Sexp::Atom(S(op), _), es @ ..] if op == "and" || op == "or" => {
+          if es.is_empty() {
+            return Err(syntax(s));
+          }
+          let vec = es.iter().map(|e| parse_expr(e, cs)).collect::<Result<_, _>>()?;
+          if op == "and" { Expr::And(vec) } else { Expr::Or(vec) }
+        },
+        [Sexp::Atom(S(letrec), _), Sexp::List(binds, _), e] if letrec == "letrec" => {
+          if binds.is_empty() {
+            return Err(syntax(s));
+          }
+          let mut vec = Vec::new();
+          for bind in binds {
+            match bind {
+              Sexp::List(fe, _) => match &fe[..] {
+                [Sexp::Atom(S(name), _), Sexp::List(f, _)] => match &f[..] {
+                  [Sexp::Atom(S(fun), _), Sexp::List(params, _), body] if fun == "fun" => {
+                    let mut ps = Vec::new();
+                    for param in params {
+                      match param {
+                        Sexp::Atom(S(x), _) => ps.push(x.to_string()),
+                        _ => return Err(syntax(s)),
+                      }
+                    }
+                    vec.push((name.to_string(), ps, parse_expr(body, cs)?));
+                  },
+                  _ => return Err(syntax(s)),
+                },
+                _ => return Err(syntax(s)),
+              },
+              _ => return Err(syntax(s)),
+            }
+          }
+          Expr::Letrec(vec, Box::new(parse_expr(e, cs)?))
+        },
+        [Sexp::Atom(S(match_), _), e, arms @ ..] if match_ == "match" => {
+          if arms.is_empty() {
+            return Err(syntax(s));
+          }
+          let mut vec = Vec::new();
+          for arm in arms {
+            match arm {
+              Sexp::List(pe, _) if pe.len() == 2 => vec.push((parse_pattern(&pe[0], cs)?, parse_expr(&pe[1], cs)?)),
+              _ => return Err(syntax(s)),
+            }
+          }
+          Expr::Match(Box::new(parse_expr(e, cs)?), vec)
+        },
+        [Sexp::Atom(S(try_), _), e, Sexp::List(catch, _)] if try_ == "try" => {
+          match &catch[..] {
+            [Sexp::Atom(S(catch_), _), Sexp::Atom(S(x), _), handler] if catch_ == "catch" => {
+              Expr::Try(Box::new(parse_expr(e, cs)?), x.to_string(), Box::new(parse_expr(handler, cs)?))
+            },
+            _ => return Err(syntax(s)),
+          }
+        },
+        [Sexp::Atom(S(loop_), _), e] if loop_ == "loop" => Expr::Loop(Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(break_), _), e] if break_ == "break" => Expr::Break(Box::new(parse_expr(e, cs)?)),
+        [Sexp::Atom(S(let_), _), Sexp::List(binds, _), e] if let_ == "let" => {
+          if binds.is_empty() {
+            return Err(syntax(s));
+          }
+          let mut vec = Vec::new();
+          for bind in binds {
+            vec.push(parse_bind(bind, cs)?);
+          }
+          Expr::Let(vec, Box::new(parse_expr(e, cs)?))
+        },
+        [Sexp::Atom(S(tuple), _), es @ ..] if tuple == "tuple" => {
+          let mut vec = Vec::new();
+          for e in es {
+            vec.push(parse_expr(e, cs)?);
+          }
+          Expr::Tuple(vec)
+        },
+        [Sexp::Atom(S(index), _), e1, e2] if index == "index" => Expr::Index(Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?)),
+        [Sexp::Atom(S(setindex), _), e1, e2, e3] if setindex == "setindex!" => Expr::SetIndex(Box::new(parse_expr(e1, cs)?), Box::new(parse_expr(e2, cs)?), Box::new(parse_expr(e3, cs)?)),
+        [Sexp::Atom(S(lambda), _), Sexp::List(params, _), body] if lambda == "lambda" => {
+          let mut v = Vec::<String>::new();
+          for param in params {
+            match param {
+              Sexp::Atom(S(n), _) => v.push(n.to_string()),
+              _ => return Err(syntax(s)),
+            }
+          }
+          Expr::Lambda(v, Box::new(parse_expr(body, cs)?))
+        },
+        [Sexp::Atom(S(func_name), _), es @ ..] => {
+          let mut vec = Vec::new();
+          for e in es {
+            vec.push(parse_expr(e, cs)?);
+          }
+          Expr::Funccall(func_name.to_string(), vec)
+        },
+        [f @ Sexp::List(_, _), es @ ..] => {
+          let mut vec = Vec::new();
+          for e in es {
+            vec.push(parse_expr(e, cs)?);
+          }
+          Expr::Call(Box::new(parse_expr(f, cs)?), vec)
+        },
+        _ => return Err(syntax(s)),
+      }
+    },
+  };
+  Ok(Expr::At(s.span(), Box::new(e)))
+}
+
+fn parse_defn(s: &Sexp, func_table: &mut HashMap<String, usize>, cs: &[Constructor]) -> Result<(Vec<String>, Box<Expr>), CompileError> {
+  Ok(match s {
+    Sexp::List(vec, _) => {
+      match &vec[..] {
+        [Sexp::Atom(S(fun), _), Sexp::List(names, _), expr] if fun == "fun" => {
+          if names.is_empty() {
+            return Err(syntax(s));
+          }
+          let mut v = Vec::<String>::new(); 
+          for name in names {
+            match name {
+              Sexp::Atom(S(n), _) => v.push(n.to_string()),
+              _ => return Err(syntax(s)),
+            }
+          }
+          match &names[0] {
+            Sexp::Atom(S(n), span) => {
+              check_name(n).map_err(|e| e.at(*span))?;
+              if func_table.contains_key(n) {
+                return Err(CompileError::DuplicateFunction(n.to_string()).at(*span));
+              }
+              func_table.insert(n.to_string(), names.len() - 1)
+            },
+            _ => return Err(syntax(s)),
+          };
+          (v, Box::new(parse_expr(expr, cs)?))
+        },
+        _ => return Err(syntax(s)),
+      }
+    }
+    _ => return Err(syntax(s)),
+  })
+}
+
+// Defines struct type `id`: the constructor `name`, the accessors
+// `name-field` and the predicate `name?`. Struct objects have kind 5 and the
+// struct id above the size in their header. Returns the definitions and the
+// description of the struct the runtime prints it with.
+fn struct_defs(name: &str, fields: &[Sexp], id: usize, span: Span, func_table: &mut HashMap<String, usize>, cs: &mut Vec<Constructor>) -> Result<(Vec<Statement>, String), CompileError> {
+  let mut names = Vec::<String>::new();
+  for field in fields {
+    match field {
+      Sexp::Atom(S(f), fspan) if names.contains(f) => return Err(CompileError::DuplicateBinding(f.to_string()).at(*fspan)),
+      Sexp::Atom(S(f), _) => names.push(f.to_string()),
+      _ => return Err(syntax(field)),
+    }
+  }
+  let header = ((id << 32) | (names.len() << 8) | 5) as i64;
+  cs.push(Constructor { name: name.to_string(), header, size: names.len() });
+  let mut funs = vec![(name.to_string(), names.clone(), Expr::Struct(id, names.iter().map(|f| Expr::Id(f.to_string())).collect()))];
+  for (idx, f) in names.iter().enumerate() {
+    let get = Expr::UnOp(Op1::GetField(header, idx), Box::new(Expr::Id("s".to_string())));
+    funs.push((format!("{}-{}", name, f), vec!["s".to_string()], get));
+  }
+  funs.push((format!("{}?", name), vec!["s".to_string()], Expr::UnOp(Op1::IsStruct(header), Box::new(Expr::Id("s".to_string())))));
+  let mut desc = name.to_string();
+  for f in &names {
+    desc.push(' ');
+    desc.push_str(f);
+  }
+  Ok((fun_defs(funs, span, func_table)?, desc))
+}
+
+fn fun_defs(funs: Vec<(String, Vec<String>, Expr)>, span: Span, func_table: &mut HashMap<String, usize>) -> Result<Vec<Statement>, CompileError> {
+  let mut v = Vec::new();
+  for (f, params, body) in funs {
+    if func_table.contains_key(&f) {
+      return Err(CompileError::DuplicateFunction(f).at(span));
+    }
+    func_table.insert(f.to_string(), params.len());
+    let mut fnames = vec![f];
+    fnames.extend(params);
+    v.push(Statement::Definition(fnames, Box::new(body), span));
+  }
+  Ok(v)
+}
+
+// (struct name (field ...)) defines one struct type; (data name (variant
+// field ...) ...) defines a struct type per variant, with consecutive ids, and
+// the predicate `name?` that accepts any of them. `id` is the first free
+// struct id; returns the definitions and the descriptions of the new types.
+fn parse_type(s: &Sexp, id: usize, func_table: &mut HashMap<String, usize>, cs: &mut Vec<Constructor>) -> Result<(Vec<Statement>, Vec<String>), CompileError> {
+  Ok(match s {
+    Sexp::List(vec, _) => {
+      match &vec[..] {
+        [Sexp::Atom(S(kw), _), Sexp::Atom(S(name), _), Sexp::List(fields, _)] if kw == "struct" => {
+          let (defs, desc) = struct_defs(name, fields, id, s.span(), func_table, cs)?;
+          (defs, vec![desc])
+        },
+        [Sexp::Atom(S(kw), _), Sexp::Atom(S(name), _), variants @ ..] if kw == "data" && !variants.is_empty() => {
+          let mut defs = Vec::new();
+          let mut descs = Vec::new();
+          for variant in variants {
+            match variant {
+              Sexp::List(items, _) => match &items[..] {
+                [Sexp::Atom(S(vname), _), fields @ ..] => {
+                  let (vdefs, desc) = struct_defs(vname, fields, id + descs.len(), variant.span(), func_table, cs)?;
+                  defs.extend(vdefs);
+                  descs.push(desc);
+                },
+                _ => return Err(syntax(s)),
+              },
+              _ => return Err(syntax(s)),
+            }
+          }
+          let is = Expr::UnOp(Op1::IsData(id as i64, (id + descs.len() - 1) as i64), Box::new(Expr::Id("s".to_string())));
+          defs.extend(fun_defs(vec![(format!("{}?", name), vec!["s".to_string()], is)], s.span(), func_table)?);
+          (defs, descs)
+        },
+        _ => return Err(syntax(s)),
+      }
+    },
+    _ => return Err(syntax(s)),
+  })
+}
+
+// Parses every top-level form, also after one of them turned out invalid, and
+// returns the statements of the valid ones with the errors of the others.
+// The arities of the functions go to func_table and the constructors of the
+// struct types to cs.
+fn parse_prog(s: &Sexp, func_table: &mut HashMap<String, usize>, cs: &mut Vec<Constructor>) -> (Vec<Statement>, Vec<CompileError>) {
+  let mut v = Vec::<Statement>::new();
+  let mut errors = Vec::new();
+  let (defns, expr) = match s {
+    Sexp::List(vec, _) if !vec.is_empty() => (&vec[..vec.len() - 1], &vec[vec.len() - 1]),
+    _ => {
+      errors.push(CompileError::Syntax("no main expression".to_string()).at(s.span()));
+      return (v, errors);
+    },
+  };
+  // types first, so that patterns anywhere can name their constructors;
+  // the main expression starts by describing the structs to the runtime
+  let is_type = |defn: &Sexp| matches!(defn, Sexp::List(items, _) if matches!(items.first(), Some(Sexp::Atom(S(kw), _)) if kw == "struct" || kw == "data"));
+  let mut infos = Vec::<Expr>::new();
+  for defn in defns.iter().filter(|defn| is_type(defn)) {
+    match parse_type(defn, infos.len(), func_table, cs) {
+      Ok((defs, descs)) => {
+        v.extend(defs);
+        for desc in descs {
+          infos.push(Expr::UnOp(Op1::StructInfo(infos.len()), Box::new(Expr::Str(desc))));
+        }
+      },
+      Err(e) => errors.push(e),
+    }
+  }
+  for defn in defns.iter().filter(|defn| !is_type(defn)) {
+    match parse_defn(defn, func_table, cs) {
+      Ok((names, body)) => v.push(Statement::Definition(names, body, defn.span())),
+      Err(e) => errors.push(e),
+    }
+  }
+  match parse_expr(expr, cs) {
+    Ok(main) if infos.is_empty() => v.push(Statement::Expression(Box::new(main), expr.span())),
+    Ok(main) => {
+      infos.push(main);
+      v.push(Statement::Expression(Box::new(Expr::Block(infos)), expr.span()));
+    },
+    Err(e) => errors.push(e),
+  }
+  (v, errors)
+}
+
+// What a name in scope is, as far as check_expr is concerned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Binding {
+  Local,      // bound by let, match or catch
+  Param,      // a parameter of the enclosing function or lambda
+  Captured,   // copied into the enclosing lambda or letrec function
+  Fun(usize), // a letrec function, with its arity
+}
+
+// Finds the errors compile_to_instrs would stop at, in all of e: unbound names,
+// calls with the wrong number of arguments, keywords used as names, break
+// where it cannot leave to a loop, input inside functions and literals out of
+// range. Errors are located at the innermost expression with a span.
+fn check_expr(e: &Expr, scope: &HashMap<String, Binding>, funcs: &HashMap<String, usize>, span: Span, in_loop: bool, in_fun: bool, errors: &mut Vec<CompileError>) {
+  match e {
+    Expr::Number(n) => {
+      if *n < -4611686018427387904 || *n > 4611686018427387903 {
+        errors.push(CompileError::LiteralOutOfRange(n.to_string()).at(span));
+      }
+    },
+    Expr::Float(_) | Expr::Str(_) | Expr::NIL | Expr::TRUE | Expr::FALSE => {},
+    Expr::INPUT => {
+      if in_fun {
+        errors.push(CompileError::InputInFunction.at(span));
+      }
+    },
+    Expr::Id(x) => {
+      if let Err(err) = check_name(x) {
+        errors.push(err.at(span));
+      } else if !scope.contains_key(x) && !funcs.contains_key(x) {
+        errors.push(CompileError::UnboundVariable(x.to_string()).at(span));
+      }
+    },
+    Expr::Set(x, e1) => {
+      // only let, match and catch variables can be assigned, and not from a
+      // lambda or letrec function, which only has a copy of them
+      if let Err(err) = check_name(x) {
+        errors.push(err.at(span));
+      } else if scope.get(x) == Some(&Binding::Captured) {
+        errors.push(CompileError::AssignCaptured(x.to_string()).at(span));
+      } else if scope.get(x) != Some(&Binding::Local) {
+        errors.push(CompileError::UnboundVariable(x.to_string()).at(span));
+      }
+      check_expr(e1, scope, funcs, span, in_loop, in_fun, errors);
+    },
+    Expr::UnOp(_, e1) => check_expr(e1, scope, funcs, span, in_loop, in_fun, errors),
+    Expr::BinOp(_, e1, e2) => {
+      check_expr(e1, scope, funcs, span, in_loop, in_fun, errors);
+      check_expr(e2, scope, funcs, span, in_loop, in_fun, errors);
+    },
+    Expr::If(e1, e2, e3) => {
+      for e in [e1, e2, e3] {
+        check_expr(e, scope, funcs, span, in_loop, in_fun, errors);
+      }
+    },
+    Expr::Block(vec) | Expr::And(vec) | Expr::Or(vec) => {
+      for e in vec {
+        check_expr(e, scope, funcs, span, in_loop, in_fun, errors);
+      }
+    },
+    Expr::Loop(body) => check_expr(body, scope, funcs, span, true, in_fun, errors),
+    Expr::Break(body) => {
+      if !in_loop {
+        errors.push(CompileError::BreakOutsideLoop.at(span));
+      }
+      check_expr(body, scope, funcs, span, in_loop, in_fun, errors);
+    },
+    Expr::Let(binds, body) => {
+      let mut nscope = scope.clone();
+      let mut names = Vec::<&String>::new();
+      for (p, e1) in binds {
+        check_expr(e1, &nscope, funcs, span, in_loop, in_fun, errors);
+        let xs = match p {
+          Pattern::Tuple(ps) => ps.iter().filter_map(|x| if let Pattern::Var(x) = x { Some(x) } else { None }).collect(),
+          Pattern::Var(x) => vec![x],
+          _ => Vec::new(),
+        };
+        for x in xs {
+          if let Err(err) = check_name(x) {
+            errors.push(err.at(span));
+            continue;
+          }
+          // rebinding a name of an enclosing let is shadowing, not a duplicate
+          if names.contains(&x) && !matches!(scope.get(x), Some(Binding::Local | Binding::Captured)) {
+            errors.push(CompileError::DuplicateBinding(x.to_string()).at(span));
+          }
+          names.push(x);
+          nscope.insert(x.to_string(), Binding::Local);
+        }
+      }
+      check_expr(body, &nscope, funcs, span, in_loop, in_fun, errors);
+    },
+    Expr::Match(e1, arms) => {
+      check_expr(e1, scope, funcs, span, in_loop, in_fun, errors);
+      for (p, body) in arms {
+        let mut nscope = scope.clone();
+        check_pattern(p, &mut Vec::new(), &mut nscope, span, errors);
+        check_expr(body, &nscope, funcs, span, in_loop, in_fun, errors);
+      }
+    },
+    Expr::Try(body, x, handler) => {
+      check_expr(body, scope, funcs, span, in_loop, in_fun, errors);
+      check_expr(handler, &scope.update(x.to_string(), Binding::Local), funcs, span, in_loop, in_fun, errors);
+    },
+    Expr::Tuple(vec) | Expr::Struct(_, vec) => {
+      for e in vec {
+        check_expr(e, scope, funcs, span, false, in_fun, errors);
+      }
+    },
+    Expr::Index(e1, e2) => {
+      check_expr(e1, scope, funcs, span, false, in_fun, errors);
+      check_expr(e2, scope, funcs, span, false, in_fun, errors);
+    },
+    Expr::SetIndex(e1, e2, e3) => {
+      for e in [e1, e2, e3] {
+        check_expr(e, scope, funcs, span, false, in_fun, errors);
+      }
+    },
+    Expr::Lambda(params, body) => {
+      // the variables of the enclosing scope are copied into the closure
+      let mut nscope: HashMap<String, Binding> = scope.iter().map(|(x, b)| (x.to_string(), if let Binding::Fun(_) = b { *b } else { Binding::Captured })).collect();
+      check_params(params, &mut nscope, span, errors);
+      check_expr(body, &nscope, funcs, span, false, true, errors);
+    },
+    Expr::Call(f, args) => {
+      check_expr(f, scope, funcs, span, false, in_fun, errors);
+      for arg in args {
+        check_expr(arg, scope, funcs, span, false, in_fun, errors);
+      }
+    },
+    Expr::Funccall(name, args) => {
+      let arity = if name == "print" {
+        Some(1)
+      } else {
+        match scope.get(name) {
+          Some(Binding::Fun(n)) => Some(*n),
+          Some(_) => None, // a closure call, checked when it runs
+          None => match funcs.get(name) {
+            Some(n) => Some(*n),
+            None => {
+              errors.push(CompileError::UnboundFunction(name.to_string()).at(span));
+              None
+            },
+          },
+        }
+      };
+      if let Some(n) = arity {
+        if args.len() != n {
+          errors.push(CompileError::ArityMismatch(name.to_string(), n, args.len()).at(span));
+        }
+      }
+      for arg in args {
+        check_expr(arg, scope, funcs, span, false, in_fun, errors);
+      }
+    },
+    Expr::Letrec(group, body) => {
+      let mut nscope = scope.clone();
+      let mut names = Vec::<&String>::new();
+      for (f, params, _) in group {
+        if names.contains(&f) {
+          errors.push(CompileError::DuplicateFunction(f.to_string()).at(span));
+        }
+        names.push(f);
+        nscope.insert(f.to_string(), Binding::Fun(params.len()));
+      }
+      for (_, params, fbody) in group {
+        // lifted to the top level, with the captured variables passed as
+        // arguments, so assigning them would only change the copy
+        let mut fscope: HashMap<String, Binding> = nscope.iter().map(|(x, b)| (x.to_string(), if let Binding::Fun(_) = b { *b } else { Binding::Captured })).collect();
+        check_params(params, &mut fscope, span, errors);
+        check_expr(fbody, &fscope, funcs, span, false, true, errors);
+      }
+      check_expr(body, &nscope, funcs, span, in_loop, in_fun, errors);
+    },
+    Expr::At(span, e1) => check_expr(e1, scope, funcs, *span, in_loop, in_fun, errors),
+  }
+}
+
+fn check_params(params: &[String], scope: &mut HashMap<String, Binding>, span: Span, errors: &mut Vec<CompileError>) {
+  let mut seen = Vec::<&String>::new();
+  for x in params {
+    if let Err(err) = check_name(x) {
+      errors.push(err.at(span));
+    } else if seen.contains(&x) {
+      errors.push(CompileError::DuplicateBinding(x.to_string()).at(span));
+    }
+    seen.push(x);
+    scope.insert(x.to_string(), Binding::Param);
+  }
+}
+
+fn check_pattern(p: &Pattern, names: &mut Vec<String>, scope: &mut HashMap<String, Binding>, span: Span, errors: &mut Vec<CompileError>) {
+  match p {
+    Pattern::Num(n) => {
+      if *n < -4611686018427387904 || *n > 4611686018427387903 {
+        errors.push(CompileError::LiteralOutOfRange(n.to_string()).at(span));
+      }
+    },
+    Pattern::Var(x) => {
+      if names.contains(x) {
+        errors.push(CompileError::DuplicateBinding(x.to_string()).at(span));
+      }
+      names.push(x.to_string());
+      scope.insert(x.to_string(), Binding::Local);
+    },
+    Pattern::Tuple(ps) | Pattern::Struct(_, ps) => {
+      for p in ps {
+        check_pattern(p, names, scope, span, errors);
+      }
+    },
+    Pattern::Wildcard | Pattern::Bool(_) | Pattern::Nil => {},
+  }
+}
+
+// Checks every definition and the main expression, so that all the errors of
+// the program are reported before any code is generated.
+fn check_prog(prog: &[Statement], funcs: &HashMap<String, usize>) -> Vec<CompileError> {
+  let mut errors = Vec::new();
+  for stmt in prog {
+    match stmt {
+      Statement::Definition(names, body, span) => {
+        let mut scope = HashMap::new();
+        check_params(&names[1..], &mut scope, *span, &mut errors);
+        check_expr(body, &scope, funcs, *span, false, true, &mut errors);
+      },
+      Statement::Expression(body, span) => check_expr(body, &HashMap::new(), funcs, *span, false, false, &mut errors),
+    }
+  }
+  errors
+}
+
+// make sure `words` words fit between r15 and the heap end (r14), running the
+// collector if they do not. `used` is how far below rsp live temporaries reach.
+// Reports out of memory if they still do not fit after collecting.
+fn heap_check(words: i64, used: i64, dep: usize, l: &mut i64) -> Vec<Instr> {
+  let mut v = Vec::<Instr>::new();
+  v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(words * 8)));
+  v.extend(heap_check_rbx(used, dep, l));
+  v
+}
+
+// same as heap_check, for a size computed at runtime: rbx holds the number of
+// bytes needed (the collector preserves it)
+fn heap_check_rbx(used: i64, dep: usize, l: &mut i64) -> Vec<Instr> {
+  let mut v = Vec::<Instr>::new();
+  let pad = (dep as i64 * 8 + used + 8) % 16 == 0;
+  v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Reg(Reg::RFIFTHTEEN)));
+  v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Reg(Reg::RBX)));
+  v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Reg(Reg::RFOURTEEN)));
+  v.push(Instr::Jle(Label::LName(format!("label{}", *l)))); // enough room, skip gc
+  v.push(Instr::IMov(Val::RegOnset(Reg::RSP, used + 8), Val::Reg(Reg::RDI)));
+  v.push(Instr::IMov(Val::Reg(Reg::RDI), Val::Reg(Reg::RFIFTHTEEN)));
+  if pad {
+    v.push(Instr::ISub(Val::Reg(Reg::RSP), Val::Imm(8)));
+  }
+  v.push(Instr::ISub(Val::Reg(Reg::RSP), Val::Imm(used + 8)));
+  v.push(Instr::IMov(Val::Reg(Reg::RSI), Val::Reg(Reg::RSP)));
+  v.push(Instr::IMov(Val::Reg(Reg::RDX), Val::Reg(Reg::RTWELVE)));
+  v.push(Instr::Call(Label::LName("snek_gc".to_string())));
+  v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm(used + 8)));
+  if pad {
+    v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm(8)));
+  }
+  v.push(Instr::IMov(Val::Reg(Reg::RFIFTHTEEN), Val::Reg(Reg::RAX)));
+  v.push(Instr::IMov(Val::Reg(Reg::RDI), Val::RegOnset(Reg::RSP, used + 8)));
+  v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Reg(Reg::RBX)));
+  v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Reg(Reg::RFOURTEEN)));
+  v.push(Instr::Jg(Label::OUTOFMEMORY));
+  v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+  *l += 1;
+  v
+}
+
+fn compile_to_instrs(e: &Expr, si: i64, ons: i64, env: &HashMap<String, i64>, v_args: &HashMap<String, usize>, func_table: &HashMap<String, usize>, cx: &Context, l: &mut i64, bl: i64, dep: usize, is_defn: bool, tail: bool) -> Result<Vec<Instr>, CompileError> {
+  let mut v = Vec::<Instr>::new();
+  match e {
+    Expr::Number(n) => {
+      if *n < -4611686018427387904 || *n > 4611686018427387903 {
+        return Err(CompileError::LiteralOutOfRange(n.to_string()));
+      }
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm((*n) * 2)));
+    },
+    Expr::Float(f) => {
+      // header, then the bits of the f64
+      v.extend(heap_check(2, ons, dep, l));
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm((1 << 8) | 4)));
+      v.push(Instr::IMov(Val::RegSet(Reg::RFIFTHTEEN), Val::Reg(Reg::RAX)));
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(f.to_bits() as i64)));
+      v.push(Instr::IMov(Val::RegOffset(Reg::RFIFTHTEEN, 8), Val::Reg(Reg::RAX)));
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Reg(Reg::RFIFTHTEEN)));
+      v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Imm(1)));
+      v.push(Instr::IAdd(Val::Reg(Reg::RFIFTHTEEN), Val::Imm(16)));
+    },
+    Expr::Str(st) => {
+      // header, then the bytes packed little-endian into zero-padded words
+      let bytes = st.as_bytes();
+      let words = (bytes.len() + 7) / 8;
+      v.extend(heap_check((words + 1) as i64, ons, dep, l));
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(((bytes.len() << 8) | 2) as i64)));
+      v.push(Instr::IMov(Val::RegSet(Reg::RFIFTHTEEN), Val::Reg(Reg::RAX)));
+      for (idx, chunk) in bytes.chunks(8).enumerate() {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(i64::from_le_bytes(word))));
+        v.push(Instr::IMov(Val::RegOffset(Reg::RFIFTHTEEN, (idx * 8 + 8) as i64), Val::Reg(Reg::RAX)));
+      }
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Reg(Reg::RFIFTHTEEN)));
+      v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Imm(1)));
+      v.push(Instr::IAdd(Val::Reg(Reg::RFIFTHTEEN), Val::Imm(((words + 1) * 8) as i64)));
+    },
+    Expr::NIL => v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(1))),
+    Expr::TRUE => v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7))),
+    Expr::FALSE => v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3))),
+    Expr::INPUT => {
+      if is_defn {
+        return Err(CompileError::InputInFunction);
+      }
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Reg(Reg::RDI)))
+    },
+    Expr::UnOp(op, subexpr) => {
+      v.extend(compile_to_instrs(subexpr, si, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, false)?);
+      match op {
+        Op1::Add1 | Op1::Sub1 if cx.opts.bignum => {
+          // fast path on fixnums, the runtime handles bignums and overflow
+          let curr_l = *l;
+          *l += 2;
+          v.push(Instr::IMov(Val::RegOnset(Reg::RSP, ons + 8), Val::Reg(Reg::RAX)));
+          v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", curr_l))));
+          if let Op1::Add1 = op {
+            v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Imm(2)));
+          } else {
+            v.push(Instr::ISub(Val::Reg(Reg::RAX), Val::Imm(2)));
+          }
+          v.push(Instr::Jo(Label::LName(format!("label{}", curr_l))));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", curr_l + 1))));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", curr_l))));
+          let code = if let Op1::Add1 = op { NUM_PLUS } else { NUM_MINUS };
+          v.extend(call_num_op(code, Val::RegOnset(Reg::RSP, ons + 8), Val::Imm(2), ons + 8, dep));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", curr_l + 1))));
+        },
+        Op1::Add1 => {
+          v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::TYPEERROR));
+          v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Imm(2)));
+          v.push(Instr::Jo(Label::OVERFLOW));
+        },
+        Op1::Sub1 => {
+          v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::TYPEERROR));
+          v.push(Instr::ISub(Val::Reg(Reg::RAX), Val::Imm(2)));
+          v.push(Instr::Jo(Label::OVERFLOW));
+        },
+        Op1::IsNum if cx.opts.bignum => {
+          // a fixnum, or a heap object of kind 3
+          v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Je(Label::LName(format!("label{}", *l + 1))));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+          v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", *l))));
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Je(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::And(Val::Reg(Reg::RBX), Val::Imm(255)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(3)));
+          v.push(Instr::Je(Label::LName(format!("label{}", *l + 1))));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 2))));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 2))));
+          *l += 3;
+        },
+        Op1::IsNum => {
+          v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", *l)))); // is not num, return false
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1)))); // is num, jmp out
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          *l += 2;
+        },
+        Op1::IsBool => {
+          v.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Je(Label::LName(format!("label{}", *l)))); // is bool, return true
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1)))); // is not bool, jmp out
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          *l += 2;
+        },
+        Op1::IsNil => {
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Je(Label::LName(format!("label{}", *l)))); // is nil, return true
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          *l += 1;
+        },
+        Op1::IsTuple => {
+          // a non-nil heap value with kind 0 in its header
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+          v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", *l))));
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Je(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::Test(Val::Reg(Reg::RBX), Val::Imm(255)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1))));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          *l += 2;
+        },
+        Op1::IsFloat => {
+          // a non-nil heap value with kind 4 in its header
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+          v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", *l))));
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Je(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::And(Val::Reg(Reg::RBX), Val::Imm(255)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(4)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1))));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          *l += 2;
+        },
+        Op1::IsStruct(header) => {
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+          v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", *l))));
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Je(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::IMov(Val::Reg(Reg::RCX), Val::Imm(*header)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Reg(Reg::RCX)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1))));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          *l += 2;
+        },
+        Op1::IsData(first, last) => {
+          // a struct whose id is one of the variants
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+          v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", *l))));
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Je(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::IMov(Val::Reg(Reg::RCX), Val::Reg(Reg::RBX)));
+          v.push(Instr::And(Val::Reg(Reg::RCX), Val::Imm(255)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RCX), Val::Imm(5)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", *l))));
+          v.push(Instr::Sar(Val::Reg(Reg::RBX), Val::Imm(32)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(*first)));
+          v.push(Instr::Jl(Label::LName(format!("label{}", *l))));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(*last)));
+          v.push(Instr::Jg(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1))));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          *l += 2;
+        },
+        Op1::GetField(header, idx) => {
+          v.extend(check_kind(5));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::IMov(Val::Reg(Reg::RCX), Val::Imm(*header)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Reg(Reg::RCX)));
+          v.push(Instr::Jne(Label::TYPEERROR));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RAX, (*idx * 8 + 7) as i64)));
+        },
+        Op1::Raise => {
+          // snek_raise does not return
+          v.extend(call_runtime("snek_raise", vec![Val::Reg(Reg::RAX), Val::Reg(Reg::RFIFTHTEEN)], ons, dep));
+        },
+        Op1::StructInfo(id) => {
+          v.extend(call_runtime("snek_struct_info", vec![Val::Imm(*id as i64), Val::Reg(Reg::RAX)], ons, dep));
+        },
+        Op1::IntToFloat => {
+          v.extend(call_num_op(NUM_TO_FLOAT, Val::Reg(Reg::RAX), Val::Imm(0), ons, dep));
+        },
+        Op1::FloatToInt => {
+          v.extend(call_num_op(NUM_TO_INT, Val::Reg(Reg::RAX), Val::Imm(0), ons, dep));
+        },
+        Op1::Len => {
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+          v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::TYPEERROR));
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Je(Label::NILREF));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(255)));
+          v.push(Instr::Jne(Label::TYPEERROR));
+          v.push(Instr::Sar(Val::Reg(Reg::RAX), Val::Imm(7)));
+        },
+        Op1::TypeOf => {
+          // 0 number, 1 boolean, 2 nil, then 3 + the kind of a heap object
+          let end = format!("label{}", *l);
+          *l += 1;
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(0)));
+          v.push(Instr::Test(Val::Reg(Reg::RBX), Val::Imm(1)));
+          v.push(Instr::Je(Label::LName(end.to_string())));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(2)));
+          v.push(Instr::Test(Val::Reg(Reg::RBX), Val::Imm(2)));
+          v.push(Instr::Jne(Label::LName(end.to_string())));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(4)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+          v.push(Instr::Je(Label::LName(end.to_string())));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOnset(Reg::RBX, 1)));
+          v.push(Instr::And(Val::Reg(Reg::RAX), Val::Imm(255)));
+          // bignums (kind 3) are numbers: -3 + 3 = 0
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(-3)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          *l += 1;
+          v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Sal(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Nothing(Label::LName(end)));
+        },
+        Op1::Not => {
+          v.extend(check_bool());
+          v.push(Instr::Xor(Val::Reg(Reg::RAX), Val::Imm(4)));
+        },
+        Op1::StrLen => {
+          v.extend(check_kind(2));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::Sar(Val::Reg(Reg::RAX), Val::Imm(7)));
+        },
+      }
+    },
+    Expr::BinOp(op, subexpr1, subexpr2) => {
+      // numeric operators take a fast path when both operands are fixnums and
+      // call the runtime otherwise (floats, bignums), which also checks the types
+      let num_code = match op {
+        Op2::Plus => Some(NUM_PLUS),
+        Op2::Minus => Some(NUM_MINUS),
+        Op2::Times => Some(NUM_TIMES),
+        Op2::Divide => Some(NUM_DIVIDE),
+        Op2::Mod => Some(NUM_MOD),
+        Op2::Lt => Some(NUM_LT),
+        Op2::Gt => Some(NUM_GT),
+        Op2::Ge => Some(NUM_GE),
+        Op2::Le => Some(NUM_LE),
+        Op2::Eq => Some(NUM_EQ),
+        _ => None,
+      };
+      v.extend(compile_to_instrs(subexpr2, si, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, false)?);
+      // check if rax is num (exp2)
+      match op {
+        _ if num_code.is_some() => {},
+        Op2::Equal | Op2::StrAppend | Op2::MakeVec => {},
+        _ => {
+          v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::TYPEERROR));
+        },
+      }
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, si * 8), Val::Reg(Reg::RAX)));
+      v.extend(compile_to_instrs(subexpr1, si + 1, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, false)?);
+      // check if rax is num (exp1)
+      match op {
+        _ if num_code.is_some() => {},
+        Op2::Equal | Op2::StrAppend | Op2::StrRef => {},
+        _ => {
+          v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::TYPEERROR));
+        },
+      }
+      let num_l = *l;
+      let overflow = if num_code.is_some() && cx.opts.bignum {
+        Label::LName(format!("label{}", num_l))
+      } else {
+        Label::OVERFLOW
+      };
+      if num_code.is_some() {
+        *l += 2;
+        v.push(Instr::IMov(Val::RegOnset(Reg::RSP, ons + 8), Val::Reg(Reg::RAX)));
+        if let Op2::Eq = op {
+          // only floats and bignums need the runtime, other values are
+          // compared inline
+          v.extend(jump_if_num_object(&format!("label{}", num_l), l));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.extend(jump_if_num_object(&format!("label{}", num_l), l));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOnset(Reg::RSP, ons + 8)));
+        } else {
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::Or(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Test(Val::Reg(Reg::RBX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::LName(format!("label{}", num_l))));
+        }
+      }
+      match op {
+        Op2::Plus => {
+          v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Jo(overflow.clone()));
+        },
+        Op2::Minus => {
+          v.push(Instr::ISub(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Jo(overflow.clone()));
+        },
+        Op2::Times => {
+          v.push(Instr::Sar(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::IMul(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Jo(overflow.clone()));
+        },
+        Op2::Divide | Op2::Mod => {
+          // 2a / 2b leaves a / b in rax and 2 * (a mod b) in rdx
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(0)));
+          v.push(Instr::Je(Label::DIVZERO));
+          v.push(Instr::Cqo);
+          v.push(Instr::IDiv(Val::Reg(Reg::RBX)));
+          if let Op2::Divide = op {
+            v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Reg(Reg::RAX)));
+            v.push(Instr::Jo(overflow.clone()));
+          } else {
+            v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Reg(Reg::RDX)));
+          }
+        },
+        Op2::Lt => {
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Jl(Label::LName(format!("label{}", *l)))); // greater, return true
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1)))); // is not greater, jmp out
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          *l += 2;
+        },
+        Op2::Gt => {
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Jg(Label::LName(format!("label{}", *l)))); // smaller, return true
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1)))); // is not smaller, jmp out
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          *l += 2;
+        },
+        Op2::Ge => {
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Jge(Label::LName(format!("label{}", *l)))); // smaller equal, return true
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1)))); // is not smaller equal, jmp out
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          *l += 2;
+        },
+        Op2::Le => {
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Jle(Label::LName(format!("label{}", *l)))); // greater equal, return true
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1)))); // is not greater equal, jmp out
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          *l += 2;
+        },
+        Op2::Eq => {
+          // numbers with numbers, booleans with booleans and heap objects
+          // with heap objects (by reference)
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::Xor(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Test(Val::Reg(Reg::RBX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::TYPEERROR));
+          v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Je(Label::LName(format!("label{}", *l))));
+          v.push(Instr::Test(Val::Reg(Reg::RBX), Val::Imm(2)));
+          v.push(Instr::Jne(Label::TYPEERROR));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          *l += 1;
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Je(Label::LName(format!("label{}", *l)))); // equal, return true
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(3)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1)))); // is not equal, jmp out
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(7)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+          *l += 2;
+        },
+        Op2::Equal => {
+          v.extend(call_runtime("snek_equal", vec![Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, si * 8)], ons, dep));
+        },
+        Op2::StrAppend => {
+          // both strings are kept on the stack so the collector can move them
+          v.extend(check_kind(2));
+          v.push(Instr::IMov(Val::RegOnset(Reg::RSP, ons + 8), Val::Reg(Reg::RAX)));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.extend(check_kind(2));
+          // bytes needed: header + total length rounded up to words
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::Sar(Val::Reg(Reg::RBX), Val::Imm(8)));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOnset(Reg::RSP, ons + 8)));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::Sar(Val::Reg(Reg::RAX), Val::Imm(8)));
+          v.push(Instr::IAdd(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::IAdd(Val::Reg(Reg::RBX), Val::Imm(15)));
+          v.push(Instr::And(Val::Reg(Reg::RBX), Val::Imm(-8)));
+          v.extend(heap_check_rbx(ons + 8, dep, l));
+          v.extend(call_runtime("snek_string_append", vec![Val::RegOnset(Reg::RSP, ons + 8), Val::RegOffset(Reg::RSP, si * 8), Val::Reg(Reg::RFIFTHTEEN)], ons + 8, dep));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::Sar(Val::Reg(Reg::RBX), Val::Imm(8)));
+          v.push(Instr::IAdd(Val::Reg(Reg::RBX), Val::Imm(15)));
+          v.push(Instr::And(Val::Reg(Reg::RBX), Val::Imm(-8)));
+          v.push(Instr::IAdd(Val::Reg(Reg::RFIFTHTEEN), Val::Reg(Reg::RBX)));
+        },
+        Op2::MakeVec => {
+          // a tuple of e1 copies of e2, filled in a loop
+          let curr_l = *l;
+          *l += 2;
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(0)));
+          v.push(Instr::Jl(Label::TYPEERROR));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(1 << 40)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Reg(Reg::RBX)));
+          v.push(Instr::Jge(Label::OUTOFMEMORY));
+          v.push(Instr::IMov(Val::RegOnset(Reg::RSP, ons + 8), Val::Reg(Reg::RAX)));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::Sal(Val::Reg(Reg::RBX), Val::Imm(2)));
+          v.push(Instr::IAdd(Val::Reg(Reg::RBX), Val::Imm(8)));
+          v.extend(heap_check_rbx(ons + 8, dep, l));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOnset(Reg::RSP, ons + 8)));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::Sal(Val::Reg(Reg::RBX), Val::Imm(7)));
+          v.push(Instr::IMov(Val::RegSet(Reg::RFIFTHTEEN), Val::Reg(Reg::RBX)));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::IMov(Val::Reg(Reg::RCX), Val::Reg(Reg::RFIFTHTEEN)));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", curr_l))));
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(0)));
+          v.push(Instr::Je(Label::LName(format!("label{}", curr_l + 1))));
+          v.push(Instr::IAdd(Val::Reg(Reg::RCX), Val::Imm(8)));
+          v.push(Instr::IMov(Val::RegSet(Reg::RCX), Val::Reg(Reg::RBX)));
+          v.push(Instr::ISub(Val::Reg(Reg::RAX), Val::Imm(2)));
+          v.push(Instr::Jmp(Label::LName(format!("label{}", curr_l))));
+          v.push(Instr::Nothing(Label::LName(format!("label{}", curr_l + 1))));
+          v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Reg(Reg::RFIFTHTEEN)));
+          v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::IAdd(Val::Reg(Reg::RCX), Val::Imm(8)));
+          v.push(Instr::IMov(Val::Reg(Reg::RFIFTHTEEN), Val::Reg(Reg::RCX)));
+        },
+        Op2::StrRef => {
+          v.extend(check_kind(2));
+          // check if 0 < e2 <= length
+          v.push(Instr::IMov(Val::Reg(Reg::RSI), Val::RegOffset(Reg::RSP, si * 8)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RSI), Val::Imm(0)));
+          v.push(Instr::Jle(Label::OUTBOUNDERROR));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::Sar(Val::Reg(Reg::RBX), Val::Imm(7)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Reg(Reg::RSI)));
+          v.push(Instr::Jl(Label::OUTBOUNDERROR));
+          v.push(Instr::IMov(Val::RegOnset(Reg::RSP, ons + 8), Val::Reg(Reg::RAX)));
+          v.extend(heap_check(2, ons + 8, dep, l));
+          v.extend(call_runtime("snek_string_ref", vec![Val::RegOnset(Reg::RSP, ons + 8), Val::RegOffset(Reg::RSP, si * 8), Val::Reg(Reg::RFIFTHTEEN)], ons + 8, dep));
+          v.push(Instr::IAdd(Val::Reg(Reg::RFIFTHTEEN), Val::Imm(16)));
+        },
+      }
+      if let Some(code) = num_code {
+        v.push(Instr::Jmp(Label::LName(format!("label{}", num_l + 1))));
+        v.push(Instr::Nothing(Label::LName(format!("label{}", num_l))));
+        v.extend(call_num_op(code, Val::RegOnset(Reg::RSP, ons + 8), Val::RegOffset(Reg::RSP, si * 8), ons + 8, dep));
+        v.push(Instr::Nothing(Label::LName(format!("label{}", num_l + 1))));
+      }
+    },
+    Expr::Let(vec, body) => {
+      let mut nenv = env.clone();
+      let mut nsi = si;
+      for (p, e) in vec{
+        let names = match p {
+          Pattern::Tuple(ps) => ps.iter().filter_map(|x| if let Pattern::Var(x) = x { Some(x) } else { None }).collect(),
+          Pattern::Var(x) => vec![x],
+          _ => return Err(CompileError::Internal("let binds only names and tuples of names".to_string())),
+        };
+        for x in &names {
+          check_name(x)?;
+        }
+        v.extend(compile_to_instrs(e, nsi, ons, &nenv, v_args, func_table, cx, l, bl, dep, is_defn, false)?);
+        if let Pattern::Tuple(ps) = p {
+          // one tag check, then the header must be a tuple of this length
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+          v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+          v.push(Instr::Jne(Label::TYPEERROR));
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+          v.push(Instr::Je(Label::NILREF));
+          v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+          v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm((ps.len() << 8) as i64)));
+          v.push(Instr::Jne(Label::TUPLEARITY));
+          for (idx, sub) in ps.iter().enumerate() {
+            if let Pattern::Var(x) = sub {
+              if nenv.contains_key(x) && !env.contains_key(x) {
+                return Err(CompileError::DuplicateBinding(x.to_string()));
+              }
+              v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RAX, (idx * 8 + 7) as i64)));
+              v.push(Instr::IMov(Val::RegOffset(Reg::RSP, nsi * 8), Val::Reg(Reg::RBX)));
+              nenv = nenv.update(x.to_string(), nsi * 8);
+              nsi += 1;
+            }
+          }
+        } else {
+          let x = names[0];
+          if nenv.contains_key(x) && !env.contains_key(x) {
+            return Err(CompileError::DuplicateBinding(x.to_string()));
+          }
+          nenv = nenv.update(x.to_string(), nsi * 8);
+          v.push(Instr::IMov(Val::RegOffset(Reg::RSP, nsi * 8), Val::Reg(Reg::RAX)));
+          nsi += 1;
+        }
+      };
+      v.extend(compile_to_instrs(body, nsi, ons, &nenv, v_args, func_table, cx, l, bl, dep, is_defn, tail)?);
+    },
+    Expr::Id(s) => {
+      check_name(s)?;
+      if env.contains_key(s) {
+        v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, *env.get(s).unwrap())));
+      } else if v_args.contains_key(s) {
+        v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, ((*v_args.get(s).unwrap() + dep + 1) * 8) as i64)));
+      } else if func_table.contains_key(s) {
+        // a top-level function used as a value
+        v.extend(alloc_closure(&fun_label(s), *func_table.get(s).unwrap(), Vec::new(), ons, dep, l));
+      } else {
+        return Err(CompileError::UnboundVariable(s.to_string()));
+      }
+    },
+    Expr::Set(s, e) => {
+      check_name(s)?;
+      if !env.contains_key(s) {
+        return Err(CompileError::UnboundVariable(s.to_string()));
+      }
+      v.extend(compile_to_instrs(e, si, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, false)?);
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, *env.get(s).unwrap()), Val::Reg(Reg::RAX)));
+    },
+    Expr::If(e1, e2, e3) => {
+      v.extend(compile_to_instrs(e1, si, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, false)?);
+      // v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(1)));
+      // v.push(Instr::Je(Label::TYPEERROR)); // if not bool, jump to err
+      let v2 = compile_to_instrs(e2, si, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, tail)?;
+      let v3 = compile_to_instrs(e3, si, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, tail)?;
+      v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(3)));
+      v.push(Instr::Je(Label::LName(format!("label{}", *l)))); // if false, jmp to else
+      v.extend(v2);
+      v.push(Instr::Jmp(Label::LName(format!("label{}", *l + 1)))); // jmp to endif
+      v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+      v.extend(v3);
+      v.push(Instr::Nothing(Label::LName(format!("label{}", *l + 1))));
+      *l += 2;
+    },
+    Expr::Block(blk) => {
+      for (idx, b) in blk.iter().enumerate() {
+        v.extend(compile_to_instrs(b, si, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, tail && idx == blk.len() - 1)?);
+      }
+    },
+    Expr::And(vec) | Expr::Or(vec) => {
+      // stop at the first false (and) / true (or) operand, which is the result
+      let end = *l;
+      *l += 1;
+      let stop = if let Expr::And(_) = e { 3 } else { 7 };
+      for (idx, e1) in vec.iter().enumerate() {
+        v.extend(compile_to_instrs(e1, si, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, false)?);
+        v.extend(check_bool());
+        if idx < vec.len() - 1 {
+          v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(stop)));
+          v.push(Instr::Je(Label::LName(format!("label{}", end))));
+        }
+      }
+      v.push(Instr::Nothing(Label::LName(format!("label{}", end))));
+    },
+    Expr::Match(e1, arms) => {
+      v.extend(compile_to_instrs(e1, si, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, false)?);
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, si * 8), Val::Reg(Reg::RAX)));
+      let end = *l;
+      *l += 1;
+      for (p, body) in arms {
+        // try the arms in order; a failed test jumps to the next one
+        let fail = *l;
+        *l += 1;
+        let mut nenv = env.clone();
+        let mut nsi = si + 1;
+        let mut names = Vec::new();
+        v.extend(compile_pattern(p, si, &mut nsi, &mut nenv, &mut names, &format!("label{}", fail))?);
+        v.extend(compile_to_instrs(body, nsi, ons, &nenv, v_args, func_table, cx, l, bl, dep, is_defn, tail)?);
+        v.push(Instr::Jmp(Label::LName(format!("label{}", end))));
+        v.push(Instr::Nothing(Label::LName(format!("label{}", fail))));
+      }
+      v.push(Instr::Jmp(Label::NOMATCH));
+      v.push(Instr::Nothing(Label::LName(format!("label{}", end))));
+    },
+    Expr::Try(body, x, handler) => {
+      // The handler record takes the slots si..si+5: the enclosing handler,
+      // rsp, the address of the catch code, and rdi, r12 and r14, which the
+      // catch code restores. [r13] holds the address of the innermost
+      // record; raising a value resets rsp from it and jumps to the catch
+      // code with the value in rax and the heap pointer in r15.
+      let catch_l = *l;
+      *l += 3;
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegSet(Reg::RTHIRTEEN)));
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, si * 8), Val::Reg(Reg::RBX)));
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, (si + 1) * 8), Val::Reg(Reg::RSP)));
+      v.push(Instr::Lea(Val::Reg(Reg::RBX), Label::LName(format!("label{}", catch_l))));
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, (si + 2) * 8), Val::Reg(Reg::RBX)));
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, (si + 3) * 8), Val::Reg(Reg::RDI)));
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, (si + 4) * 8), Val::Reg(Reg::RTWELVE)));
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, (si + 5) * 8), Val::Reg(Reg::RFOURTEEN)));
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Reg(Reg::RSP)));
+      v.push(Instr::IAdd(Val::Reg(Reg::RBX), Val::Imm(si * 8)));
+      v.push(Instr::IMov(Val::RegSet(Reg::RTHIRTEEN), Val::Reg(Reg::RBX)));
+      // no tail calls (the record lives in this frame), and a break out of
+      // the body first goes through label catch_l + 2, which reinstates the
+      // enclosing handler like leaving the body normally does
+      let body_bl = if bl == -1 { -1 } else { catch_l + 2 };
+      v.extend(compile_to_instrs(body, si + 6, ons, env, v_args, func_table, cx, l, body_bl, dep, is_defn, false)?);
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, si * 8)));
+      v.push(Instr::IMov(Val::RegSet(Reg::RTHIRTEEN), Val::Reg(Reg::RBX)));
+      v.push(Instr::Jmp(Label::LName(format!("label{}", catch_l + 1))));
+      if bl != -1 {
+        v.push(Instr::Nothing(Label::LName(format!("label{}", catch_l + 2))));
+        v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, si * 8)));
+        v.push(Instr::IMov(Val::RegSet(Reg::RTHIRTEEN), Val::Reg(Reg::RBX)));
+        v.push(Instr::Jmp(Label::LName(format!("label{}", bl))));
+      }
+      v.push(Instr::Nothing(Label::LName(format!("label{}", catch_l))));
+      v.push(Instr::IMov(Val::Reg(Reg::RDI), Val::RegOffset(Reg::RSP, (si + 3) * 8)));
+      v.push(Instr::IMov(Val::Reg(Reg::RTWELVE), Val::RegOffset(Reg::RSP, (si + 4) * 8)));
+      v.push(Instr::IMov(Val::Reg(Reg::RFOURTEEN), Val::RegOffset(Reg::RSP, (si + 5) * 8)));
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, si * 8)));
+      v.push(Instr::IMov(Val::RegSet(Reg::RTHIRTEEN), Val::Reg(Reg::RBX)));
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, si * 8), Val::Reg(Reg::RAX)));
+      let nenv = env.update(x.to_string(), si * 8);
+      v.extend(compile_to_instrs(handler, si + 1, ons, &nenv, v_args, func_table, cx, l, bl, dep, is_defn, tail)?);
+      v.push(Instr::Nothing(Label::LName(format!("label{}", catch_l + 1))));
+    },
+    Expr::Loop(body) => {
+      let curr_l = *l;
+      *l += 2;
+      v.push(Instr::Nothing(Label::LName(format!("label{}", curr_l))));
+      v.extend(compile_to_instrs(body, si, ons, env, v_args, func_table, cx, l, curr_l + 1, dep, is_defn, false)?);
+      v.push(Instr::Jmp(Label::LName(format!("label{}", curr_l))));
+      v.push(Instr::Nothing(Label::LName(format!("label{}", curr_l + 1))));
+    },
+    Expr::Break(body) => {
+      if bl == -1 {
+        return Err(CompileError::BreakOutsideLoop);
+      }
+      v.extend(compile_to_instrs(body, si, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, false)?);
+      v.push(Instr::Jmp(Label::LName(format!("label{}", bl))));
+    },
+    Expr::Tuple(es) | Expr::Struct(_, es) => {
+      for (idx, e) in es.iter().enumerate() {
+        let onset = ons + ((idx * 8 + 8) as i64);
+        v.extend(compile_to_instrs(e, si, onset, env, v_args, func_table, cx, l, -1, dep, is_defn, false)?);
+        v.push(Instr::IMov(Val::RegOnset(Reg::RSP, onset), Val::Reg(Reg::RAX)));
+      }
+      let len_tp = es.len();
+      v.extend(heap_check((len_tp + 1) as i64, ons + (len_tp * 8) as i64, dep, l));
+      let header = match e {
+        Expr::Struct(id, _) => (id << 32) | (len_tp << 8) | 5,
+        _ => len_tp << 8,
+      };
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(header as i64)));
+      v.push(Instr::IMov(Val::RegSet(Reg::RFIFTHTEEN), Val::Reg(Reg::RAX)));
+      for (idx, e) in es.iter().enumerate() {
+        let onset = ons + ((idx * 8 + 8) as i64);
+        v.push(Instr::IAdd(Val::Reg(Reg::RFIFTHTEEN), Val::Imm(8)));
+        v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOnset(Reg::RSP, onset)));
+        v.push(Instr::IMov(Val::RegSet(Reg::RFIFTHTEEN), Val::Reg(Reg::RAX)));
+      }
+      v.push(Instr::IAdd(Val::Reg(Reg::RFIFTHTEEN), Val::Imm(8)));
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Reg(Reg::RFIFTHTEEN)));
+      v.push(Instr::ISub(Val::Reg(Reg::RAX), Val::Imm((8 * (len_tp + 1) - 1) as i64)))
+    },
+    Expr::Index(e1, e2) => {
+      v.extend(compile_to_instrs(e2, si, ons, env, v_args, func_table, cx, l, -1, dep, is_defn, false)?);
+      // check if rax is num (e2)
+      v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(1)));
+      v.push(Instr::Jne(Label::TYPEERROR));
+      // check if e2 > 0
+      v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(0)));
+      v.push(Instr::IMov(Val::Reg(Reg::RSI), Val::Reg(Reg::RAX)));
+      v.push(Instr::Jle(Label::OUTBOUNDERROR));
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, si * 8), Val::Reg(Reg::RAX)));
+      
+      v.extend(compile_to_instrs(e1, si + 1, ons, env, v_args, func_table, cx, l, -1, dep, is_defn, false)?);
+      // check if rax is heap-alloc (e1)
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+      v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+      v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+      v.push(Instr::Jne(Label::TYPEERROR));
+
+      // check if rax is nil (e1)
+      v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+      v.push(Instr::Je(Label::NILREF));
+
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+      // check if rax is a tuple (kind 0 in the header)
+      v.push(Instr::Test(Val::Reg(Reg::RBX), Val::Imm(255)));
+      v.push(Instr::Jne(Label::TYPEERROR));
+      // check if e2 <= RBX (size)
+      v.push(Instr::Sar(Val::Reg(Reg::RBX), Val::Imm(7)));
+      v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, si * 8)));
+      v.push(Instr::IMov(Val::Reg(Reg::RSI), Val::RegOffset(Reg::RSP, si * 8)));
+      v.push(Instr::Jl(Label::OUTBOUNDERROR));
+
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, si * 8))); // move e2 into rbx
+      v.push(Instr::Sal(Val::Reg(Reg::RBX), Val::Imm(2)));
+      v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Reg(Reg::RBX)));
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOnset(Reg::RAX, 1)));
+    },
+    Expr::SetIndex(e1, e2, e3) => {
+      v.extend(compile_to_instrs(e3, si, ons, env, v_args, func_table, cx, l, -1, dep, is_defn, false)?);
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, si * 8), Val::Reg(Reg::RAX)));
+
+      v.extend(compile_to_instrs(e2, si + 1, ons, env, v_args, func_table, cx, l, -1, dep, is_defn, false)?);
+      // check if rax is num (e2)
+      v.push(Instr::Test(Val::Reg(Reg::RAX), Val::Imm(1)));
+      v.push(Instr::Jne(Label::TYPEERROR));
+      // check if e2 > 0
+      v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(0)));
+      v.push(Instr::IMov(Val::Reg(Reg::RSI), Val::Reg(Reg::RAX)));
+      v.push(Instr::Jle(Label::OUTBOUNDERROR));
+      v.push(Instr::IMov(Val::RegOffset(Reg::RSP, (si + 1) * 8), Val::Reg(Reg::RAX)));
+
+      v.extend(compile_to_instrs(e1, si + 2, ons, env, v_args, func_table, cx, l, -1, dep, is_defn, false)?);
+      // check if rax is heap-alloc (e1)
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+      v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+      v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+      v.push(Instr::Jne(Label::TYPEERROR));
+
+      // check if rax is nil (e1)
+      v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+      v.push(Instr::Je(Label::NILREF));
+
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+      // check if rax is a tuple (kind 0 in the header)
+      v.push(Instr::Test(Val::Reg(Reg::RBX), Val::Imm(255)));
+      v.push(Instr::Jne(Label::TYPEERROR));
+      // check if e2 <= RBX (size)
+      v.push(Instr::Sar(Val::Reg(Reg::RBX), Val::Imm(7)));
+      v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, (si + 1) * 8)));
+      v.push(Instr::IMov(Val::Reg(Reg::RSI), Val::RegOffset(Reg::RSP, (si + 1) * 8)));
+      v.push(Instr::Jl(Label::OUTBOUNDERROR));
+
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, (si + 1) * 8))); // move e2 into rbx
+      v.push(Instr::Sal(Val::Reg(Reg::RBX), Val::Imm(2)));
+      v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Reg(Reg::RBX)));
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RSP, si * 8))); // move e3 into rbx
+      v.push(Instr::IMov(Val::RegOnset(Reg::RAX, 1), Val::Reg(Reg::RBX)));
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Reg(Reg::RBX)));
+    },
+    Expr::Lambda(params, body) => {
+      let mut fv = Vec::new();
+      free_vars(body, &params.iter().cloned().collect(), &mut fv);
+      let captured: Vec<String> = fv.into_iter().filter(|x| env.contains_key(x) || v_args.contains_key(x)).collect();
+      let mut l_args = HashMap::new();
+      for (idx, param) in params.iter().enumerate() {
+        check_name(param)?;
+        if l_args.contains_key(param) {
+          return Err(CompileError::DuplicateBinding(param.to_string()));
+        }
+        l_args.insert(param.to_string(), idx);
+      }
+      // captured values are copied from the closure (in rax on entry) into the first slots
+      let mut l_env = HashMap::new();
+      for (idx, x) in captured.iter().enumerate() {
+        l_env.insert(x.to_string(), (idx * 8) as i64);
+      }
+      let mut l_dep = depth(body) + captured.len() + 2;
+      if l_dep % 2 != 0 {
+        l_dep += 1;
+      }
+      let curr_l = *l;
+      *l += 2;
+      v.push(Instr::Jmp(Label::LName(format!("label{}", curr_l + 1)))); // skip over the body
+      v.push(Instr::Nothing(Label::LName(format!("label{}", curr_l))));
+      v.push(Instr::ISub(Val::Reg(Reg::RSP), Val::Imm((l_dep * 8) as i64)));
+      for idx in 0..captured.len() {
+        v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RAX, (idx * 8 + 23) as i64)));
+        v.push(Instr::IMov(Val::RegOffset(Reg::RSP, (idx * 8) as i64), Val::Reg(Reg::RBX)));
+      }
+      v.extend(compile_to_instrs(body, captured.len() as i64, 0, &l_env, &l_args, func_table, cx, l, -1, l_dep, true, true)?);
+      v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm((l_dep * 8) as i64)));
+      v.push(Instr::Ret);
+      v.push(Instr::Nothing(Label::LName(format!("label{}", curr_l + 1))));
+
+      let mut locs = Vec::new();
+      for x in &captured {
+        match env.get(x) {
+          Some(offset) => locs.push(Val::RegOffset(Reg::RSP, *offset)),
+          None => locs.push(Val::RegOffset(Reg::RSP, ((*v_args.get(x).unwrap() + dep + 1) * 8) as i64)),
+        }
+      }
+      v.extend(alloc_closure(&format!("label{}", curr_l), params.len(), locs, ons, dep, l));
+    },
+    Expr::At(span, e1) => {
+      v.extend(compile_to_instrs(e1, si, ons, env, v_args, func_table, cx, l, bl, dep, is_defn, tail).map_err(|err| err.at(*span))?);
+    },
+    Expr::Letrec(..) => return Err(CompileError::Internal("letrec should have been lifted".to_string())),
+    Expr::Call(f, args) => {
+      v.extend(compile_closure_call(f, args, si, ons, env, v_args, func_table, cx, l, dep, is_defn, tail)?);
+    },
+    Expr::Funccall(func_name, args) => {
+      if func_name == "print" {
+        if args.len() != 1 {
+          return Err(CompileError::ArityMismatch(func_name.to_string(), 1, args.len()));
+        }
+        v.extend(compile_to_instrs(&args[0], si, ons, env, v_args, func_table, cx, l, -1, dep, is_defn, false)?);
+        v.push(Instr::IMov(Val::RegOnset(Reg::RSP, ons + 8), Val::Reg(Reg::RDI)));
+        v.push(Instr::IMov(Val::Reg(Reg::RDI), Val::Reg(Reg::RAX)));
+        v.push(Instr::IMov(Val::RegOnset(Reg::RSP, ons + 16), Val::Reg(Reg::RAX)));
+        if (dep * 8 + 16 + ons as usize) % 16 == 0 {
+          v.push(Instr::ISub(Val::Reg(Reg::RSP), Val::Imm(8)));
+        }
+        v.push(Instr::ISub(Val::Reg(Reg::RSP), Val::Imm(ons + 16)));
+        v.push(Instr::Call(Label::LName("snek_print".to_string())));
+        v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm(ons + 16)));
+        if (dep * 8 + 16 + ons as usize) % 16 == 0 {
+          v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm(8)));
+        }
+        v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOnset(Reg::RSP, ons + 16)));
+        v.push(Instr::IMov(Val::Reg(Reg::RDI), Val::RegOnset(Reg::RSP, ons + 8)));
+      } else if env.contains_key(func_name) || v_args.contains_key(func_name) {
+        v.extend(compile_closure_call(&Expr::Id(func_name.to_string()), args, si, ons, env, v_args, func_table, cx, l, dep, is_defn, tail)?);
+      } else {
+        match func_table.get(func_name) {
+          Some(count) => {
+            if args.len() != *count {
+              return Err(CompileError::ArityMismatch(func_name.to_string(), *count, args.len()));
+            }
+            let slots = cx.slots;
+            let pad = (dep * 8 + ons as usize + slots * 8 + 8) % 16 == 0;
+            v.push(Instr::IMov(Val::RegOnset(Reg::RSP, ons + 8), Val::Reg(Reg::RDI)));
+            for (idx, arg) in args.iter().enumerate().rev() {
+              let onset = arg_onset(idx, ons, pad, slots);
+              v.extend(compile_to_instrs(arg, si, onset, env, v_args, func_table, cx, l, -1, dep, is_defn, false)?);
+              v.push(Instr::IMov(Val::RegOnset(Reg::RSP, onset), Val::Reg(Reg::RAX)));
+            }
+            if tail {
+              v.extend(tail_call_args(args.len(), ons, pad, dep, slots));
+              v.push(Instr::Jmp(Label::LName(fun_label(func_name))));
+            } else {
+              if pad {
+                v.push(Instr::ISub(Val::Reg(Reg::RSP), Val::Imm(8)));
+              }
+              v.push(Instr::ISub(Val::Reg(Reg::RSP), Val::Imm(ons + (slots * 8 + 8) as i64)));
+              v.push(Instr::Call(Label::LName(fun_label(func_name))));
+              v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm(ons + (slots * 8 + 8) as i64)));
+              if pad {
+                v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm(8)));
+              }
+              v.push(Instr::IMov(Val::Reg(Reg::RDI), Val::RegOnset(Reg::RSP, ons + 8)));
+            }
+          },
+          None => return Err(CompileError::UnboundFunction(func_name.to_string())),
+        }
+      }
+    },
+  }
+  below_temporaries(&mut v, ons);
+  Ok(v)
+}
+
+// The runtime's error code runs on the stack below rsp, where the live
+// temporaries of the enclosing expressions are kept (down to [rsp - ons]), and
+// a caught error continues with them. The error jumps an expression emits
+// itself (those of its subexpressions are already redirected, with their own
+// ons) go through a stub that first moves rsp below them.
+fn below_temporaries(v: &mut [Instr], ons: i64) {
+  for instr in v.iter_mut() {
+    match instr {
+      Instr::Jmp(l) | Instr::Je(l) | Instr::Jne(l) | Instr::Jg(l) | Instr::Jl(l) | Instr::Jge(l) | Instr::Jle(l) | Instr::Jo(l) if !matches!(l, Label::LName(_) | Label::Below(..)) => {
+        *l = Label::Below(Box::new(l.clone()), ons);
+      },
+      _ => (),
+    }
+  }
+}
+
+// Adds the stubs that the error jumps in v go through to `stubs`.
+fn error_stubs(v: &[Instr], stubs: &mut Vec<String>) {
+  for instr in v {
+    match instr {
+      Instr::Jmp(l) | Instr::Je(l) | Instr::Jne(l) | Instr::Jg(l) | Instr::Jl(l) | Instr::Jge(l) | Instr::Jle(l) | Instr::Jo(l) => {
+        if let Label::Below(target, n) = l {
+          let stub = format!("\n{}:\n  sub rsp, {}\n  jmp {}", label_to_str(l), n, label_to_str(target));
+          if *n > 0 && !stubs.contains(&stub) {
+            stubs.push(stub);
+          }
+        }
+      },
+      _ => (),
+    }
+  }
+}
+
+// test the value in stack slot `slot` against p, jumping to `fail` when it
+// does not match. Binders are added to env (and names); the parts of a tuple
+// that still need testing or binding are copied to the slots from nsi on.
+fn compile_pattern(p: &Pattern, slot: i64, nsi: &mut i64, env: &mut HashMap<String, i64>, names: &mut Vec<String>, fail: &str) -> Result<Vec<Instr>, CompileError> {
+  let mut v = Vec::<Instr>::new();
+  let lit = match p {
+    Pattern::Num(n) => {
+      if *n < -4611686018427387904 || *n > 4611686018427387903 {
+        return Err(CompileError::LiteralOutOfRange(n.to_string()));
+      }
+      Some(*n * 2)
+    },
+    Pattern::Bool(true) => Some(7),
+    Pattern::Bool(false) => Some(3),
+    Pattern::Nil => Some(1),
+    _ => None,
+  };
+  if let Some(n) = lit {
+    v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, slot * 8)));
+    v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(n)));
+    v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Reg(Reg::RBX)));
+    v.push(Instr::Jne(Label::LName(fail.to_string())));
+    return Ok(v);
+  }
+  match p {
+    Pattern::Var(x) => {
+      if names.contains(x) {
+        return Err(CompileError::DuplicateBinding(x.to_string()));
+      }
+      names.push(x.to_string());
+      env.insert(x.to_string(), slot * 8);
+    },
+    Pattern::Tuple(ps) | Pattern::Struct(_, ps) => {
+      // a non-nil heap value whose header says: tuple of this length, or
+      // object of this struct type
+      v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, slot * 8)));
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+      v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+      v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+      v.push(Instr::Jne(Label::LName(fail.to_string())));
+      v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+      v.push(Instr::Je(Label::LName(fail.to_string())));
+      v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+      let header = match p {
+        Pattern::Struct(header, _) => *header,
+        _ => (ps.len() << 8) as i64,
+      };
+      v.push(Instr::IMov(Val::Reg(Reg::RCX), Val::Imm(header)));
+      v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Reg(Reg::RCX)));
+      v.push(Instr::Jne(Label::LName(fail.to_string())));
+      for (idx, sub) in ps.iter().enumerate() {
+        if let Pattern::Wildcard = sub {
+          continue;
+        }
+        let sub_slot = *nsi;
+        *nsi += 1;
+        v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RSP, slot * 8)));
+        v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::RegOffset(Reg::RAX, (idx * 8 + 7) as i64)));
+        v.push(Instr::IMov(Val::RegOffset(Reg::RSP, sub_slot * 8), Val::Reg(Reg::RAX)));
+        v.extend(compile_pattern(sub, sub_slot, nsi, env, names, fail)?);
+      }
+    },
+    _ => {},
+  }
+  Ok(v)
+}
+
+// raise invalid argument unless rax is true or false
+fn check_bool() -> Vec<Instr> {
+  let mut v = Vec::<Instr>::new();
+  v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+  v.push(Instr::And(Val::Reg(Reg::RBX), Val::Imm(3)));
+  v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(3)));
+  v.push(Instr::Jne(Label::TYPEERROR));
+  v
+}
+
+// raise invalid argument unless rax points to a heap object of the given kind
+// (1 for closures, 2 for strings, 5 for structs)
+fn check_kind(kind: i64) -> Vec<Instr> {
+  let mut v = Vec::<Instr>::new();
+  v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+  v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+  v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+  v.push(Instr::Jne(Label::TYPEERROR));
+  v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+  v.push(Instr::Je(Label::TYPEERROR));
+  v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+  v.push(Instr::And(Val::Reg(Reg::RBX), Val::Imm(255)));
+  v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(kind)));
+  v.push(Instr::Jne(Label::TYPEERROR));
+  v
+}
+
+// jumps to `target` when rax holds a float or a bignum
+fn jump_if_num_object(target: &str, l: &mut i64) -> Vec<Instr> {
+  let mut v = Vec::<Instr>::new();
+  v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Imm(3)));
+  v.push(Instr::And(Val::Reg(Reg::RBX), Val::Reg(Reg::RAX)));
+  v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(1)));
+  v.push(Instr::Jne(Label::LName(format!("label{}", *l))));
+  v.push(Instr::Cmp(Val::Reg(Reg::RAX), Val::Imm(1)));
+  v.push(Instr::Je(Label::LName(format!("label{}", *l))));
+  v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RAX, 1)));
+  v.push(Instr::And(Val::Reg(Reg::RBX), Val::Imm(255)));
+  v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(3)));
+  v.push(Instr::Je(Label::LName(target.to_string())));
+  v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(4)));
+  v.push(Instr::Je(Label::LName(target.to_string())));
+  v.push(Instr::Nothing(Label::LName(format!("label{}", *l))));
+  *l += 1;
+  v
+}
+
+// operation codes understood by snek_num_op
+const NUM_PLUS: i64 = 0;
+const NUM_MINUS: i64 = 1;
+const NUM_TIMES: i64 = 2;
+const NUM_DIVIDE: i64 = 3;
+const NUM_MOD: i64 = 4;
+const NUM_LT: i64 = 5;
+const NUM_GT: i64 = 6;
+const NUM_GE: i64 = 7;
+const NUM_LE: i64 = 8;
+const NUM_EQ: i64 = 9;
+const NUM_TO_FLOAT: i64 = 10;
+const NUM_TO_INT: i64 = 11;
+
+// slow path of a numeric operator, for floats and bignums (and errors):
+// snek_num_op may allocate the result on the heap, so it also gets the heap pointer and the stack range the
+// collector scans, and returns the new heap pointer in rdx
+fn call_num_op(code: i64, a: Val, b: Val, ons: i64, dep: usize) -> Vec<Instr> {
+  let mut v = Vec::<Instr>::new();
+  let pad = (dep as i64 * 8 + ons + 8) % 16 == 0;
+  // rsp as seen by the runtime, once call_runtime has made room
+  v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::Reg(Reg::RSP)));
+  v.push(Instr::ISub(Val::Reg(Reg::RBX), Val::Imm(ons + 8 + if pad { 8 } else { 0 })));
+  v.extend(call_runtime("snek_num_op", vec![Val::Imm(code), a, b, Val::Reg(Reg::RFIFTHTEEN), Val::Reg(Reg::RBX), Val::Reg(Reg::RTWELVE)], ons, dep));
+  v.push(Instr::IMov(Val::Reg(Reg::RFIFTHTEEN), Val::Reg(Reg::RDX)));
+  v
+}
+
+// call a runtime function, passing `args` in rdi, rsi, rdx, rcx, r8 and r9, while
+// keeping rdi (input) and the stack alignment intact. `ons` is how far below
+// rsp live temporaries reach. The result is left in rax.
+fn call_runtime(name: &str, args: Vec<Val>, ons: i64, dep: usize) -> Vec<Instr> {
+  let mut v = Vec::<Instr>::new();
+  let pad = (dep as i64 * 8 + ons + 8) % 16 == 0;
+  v.push(Instr::IMov(Val::RegOnset(Reg::RSP, ons + 8), Val::Reg(Reg::RDI)));
+  for (idx, arg) in args.into_iter().enumerate() {
+    let reg = match idx {
+      0 => Reg::RDI,
+      1 => Reg::RSI,
+      2 => Reg::RDX,
+      3 => Reg::RCX,
+      4 => Reg::R8,
+      _ => Reg::R9,
+    };
+    v.push(Instr::IMov(Val::Reg(reg), arg));
+  }
+  if pad {
+    v.push(Instr::ISub(Val::Reg(Reg::RSP), Val::Imm(8)));
+  }
+  v.push(Instr::ISub(Val::Reg(Reg::RSP), Val::Imm(ons + 8)));
+  v.push(Instr::Call(Label::LName(name.to_string())));
+  v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm(ons + 8)));
+  if pad {
+    v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm(8)));
+  }
+  v.push(Instr::IMov(Val::Reg(Reg::RDI), Val::RegOnset(Reg::RSP, ons + 8)));
+  v
+}
+
+// Closure layout: header ((captured count << 8) | 1), arity, code pointer,
+// captured values. `captured` are the stack locations to copy them from.
+fn alloc_closure(code: &str, arity: usize, captured: Vec<Val>, ons: i64, dep: usize, l: &mut i64) -> Vec<Instr> {
+  let mut v = Vec::<Instr>::new();
+  let size = captured.len() + 3;
+  v.extend(heap_check(size as i64, ons, dep, l));
+  v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(((captured.len() << 8) | 1) as i64)));
+  v.push(Instr::IMov(Val::RegSet(Reg::RFIFTHTEEN), Val::Reg(Reg::RAX)));
+  v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Imm(arity as i64)));
+  v.push(Instr::IMov(Val::RegOffset(Reg::RFIFTHTEEN, 8), Val::Reg(Reg::RAX)));
+  v.push(Instr::Lea(Val::Reg(Reg::RAX), Label::LName(code.to_string())));
+  v.push(Instr::IMov(Val::RegOffset(Reg::RFIFTHTEEN, 16), Val::Reg(Reg::RAX)));
+  for (idx, loc) in captured.into_iter().enumerate() {
+    v.push(Instr::IMov(Val::Reg(Reg::RAX), loc));
+    v.push(Instr::IMov(Val::RegOffset(Reg::RFIFTHTEEN, (idx * 8 + 24) as i64), Val::Reg(Reg::RAX)));
+  }
+  v.push(Instr::IMov(Val::Reg(Reg::RAX), Val::Reg(Reg::RFIFTHTEEN)));
+  v.push(Instr::IAdd(Val::Reg(Reg::RAX), Val::Imm(1)));
+  v.push(Instr::IAdd(Val::Reg(Reg::RFIFTHTEEN), Val::Imm((size * 8) as i64)));
+  v
+}
+
+// Call a function value. Arguments are laid out as for a direct call, then
+// the closure is passed in rax so the callee can unpack its captured values.
+fn compile_closure_call(f: &Expr, args: &[Expr], si: i64, ons: i64, env: &HashMap<String, i64>, v_args: &HashMap<String, usize>, func_table: &HashMap<String, usize>, cx: &Context, l: &mut i64, dep: usize, is_defn: bool, tail: bool) -> Result<Vec<Instr>, CompileError> {
+  let mut v = Vec::<Instr>::new();
+  let slots = cx.slots;
+  let pad = (dep * 8 + ons as usize + slots * 8 + 8) % 16 == 0;
+  v.push(Instr::IMov(Val::RegOnset(Reg::RSP, ons + 8), Val::Reg(Reg::RDI)));
+  for (idx, arg) in args.iter().enumerate().rev() {
+    let onset = arg_onset(idx, ons, pad, slots);
+    v.extend(compile_to_instrs(arg, si, onset, env, v_args, func_table, cx, l, -1, dep, is_defn, false)?);
+    v.push(Instr::IMov(Val::RegOnset(Reg::RSP, onset), Val::Reg(Reg::RAX)));
+  }
+  let mut end = ons + ((slots * 8 + 8) as i64);
+  if pad {
+    end += 8;
+  }
+  v.extend(compile_to_instrs(f, si, end, env, v_args, func_table, cx, l, -1, dep, is_defn, false)?);
+  v.extend(check_kind(1));
+  // check the arity
+  v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RAX, 7)));
+  v.push(Instr::Cmp(Val::Reg(Reg::RBX), Val::Imm(args.len() as i64)));
+  v.push(Instr::Jne(Label::ARITYERROR));
+  if tail {
+    v.extend(tail_call_args(args.len(), ons, pad, dep, slots));
+    v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RAX, 15)));
+    v.push(Instr::JmpInd(Val::Reg(Reg::RBX)));
+    return Ok(v);
+  }
+  if pad {
+    v.push(Instr::ISub(Val::Reg(Reg::RSP), Val::Imm(8)));
+  }
+  v.push(Instr::ISub(Val::Reg(Reg::RSP), Val::Imm(ons + (slots * 8 + 8) as i64)));
+  v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOffset(Reg::RAX, 15)));
+  v.push(Instr::CallInd(Val::Reg(Reg::RBX)));
+  v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm(ons + (slots * 8 + 8) as i64)));
+  if pad {
+    v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm(8)));
+  }
+  v.push(Instr::IMov(Val::Reg(Reg::RDI), Val::RegOnset(Reg::RSP, ons + 8)));
+  Ok(v)
+}
+
+// Where argument idx of a call is stored below rsp before the call. Every
+// call reserves `slots` slots above the return address, with the first
+// argument nearest to it; the rdi of the caller is kept below the slots.
+fn arg_onset(idx: usize, ons: i64, pad: bool, slots: usize) -> i64 {
+  let mut onset = ons + (((slots - 1 - idx) * 8 + 16) as i64);
+  if pad {
+    onset += 8;
+  }
+  onset
+}
+
+// A call in tail position reuses the current frame: the arguments, laid out
+// below rsp as for a normal call, are moved into our own argument slots, the
+// frame is popped and the caller jumps to the callee, which returns straight
+// to our caller. Every call reserves the same number of slots, so the
+// arguments of any callee fit in the slots our caller reserved for us.
+fn tail_call_args(nargs: usize, ons: i64, pad: bool, dep: usize, slots: usize) -> Vec<Instr> {
+  let mut v = Vec::<Instr>::new();
+  for idx in 0..nargs {
+    let onset = arg_onset(idx, ons, pad, slots);
+    v.push(Instr::IMov(Val::Reg(Reg::RBX), Val::RegOnset(Reg::RSP, onset)));
+    v.push(Instr::IMov(Val::RegOffset(Reg::RSP, ((idx + dep + 1) * 8) as i64), Val::Reg(Reg::RBX)));
+  }
+  v.push(Instr::IAdd(Val::Reg(Reg::RSP), Val::Imm((dep * 8) as i64)));
+  v
+}
+
+fn instr_to_str(instr: &Instr) -> String {
+  match instr {
+    Instr::IMov(v1, v2) => format!("\nmov {}, {}", val_to_str(v1), val_to_str(v2)),
+    Instr::IAdd(v1, v2) => format!("\nadd {}, {}", val_to_str(v1), val_to_str(v2)),
+    Instr::ISub(v1, v2) => format!("\nsub {}, {}", val_to_str(v1), val_to_str(v2)),
+    Instr::IMul(v1, v2) => format!("\nimul {}, {}", val_to_str(v1), val_to_str(v2)),
+    Instr::IDiv(v1) => format!("\nidiv {}", val_to_str(v1)),
+    Instr::Cqo => format!("\ncqo"),
+    Instr::Test(v1, v2) => format!("\ntest {}, {}", val_to_str(v1), val_to_str(v2)),
+    Instr::Cmp(v1, v2) => format!("\ncmp {}, {}", val_to_str(v1), val_to_str(v2)),
+    Instr::Sal(v1, v2) => format!("\nsal {}, {}", val_to_str(v1), val_to_str(v2)),
+    Instr::Sar(v1, v2) => format!("\nsar {}, {}", val_to_str(v1), val_to_str(v2)),
+    Instr::And(v1, v2) => format!("\nand {}, {}", val_to_str(v1), val_to_str(v2)),
+    Instr::Or(v1, v2) => format!("\nor {}, {}", val_to_str(v1), val_to_str(v2)),
+    Instr::Xor(v1, v2) => format!("\nxor {}, {}", val_to_str(v1), val_to_str(v2)),
+    Instr::Push(v1) => format!("\npush {}", val_to_str(v1)),
+    Instr::Pop(v1) => format!("\npop {}", val_to_str(v1)),
+    Instr::Jmp(l1) => format!("\njmp {}", label_to_str(l1)),
+    Instr::Jne(l1) => format!("\njne {}", label_to_str(l1)),
+    Instr::Je(l1) => format!("\nje {}", label_to_str(l1)),
+    Instr::Jg(l1) => format!("\njg {}", label_to_str(l1)),
+    Instr::Jl(l1) => format!("\njl {}", label_to_str(l1)),
+    Instr::Jge(l1) => format!("\njge {}", label_to_str(l1)),
+    Instr::Jle(l1) => format!("\njle {}", label_to_str(l1)),
+    Instr::Jo(l1) => format!("\njo {}", label_to_str(l1)),
+    Instr::Nothing(l1) => format!("\n{}:", label_to_str(l1)),
+    Instr::Call(l1) => format!("\ncall {}", label_to_str(l1)),
+    Instr::CallInd(v1) => format!("\ncall {}", val_to_str(v1)),
+    Instr::JmpInd(v1) => format!("\njmp {}", val_to_str(v1)),
+    Instr::Lea(v1, l1) => format!("\nlea {}, [rel {}]", val_to_str(v1), label_to_str(l1)),
+    Instr::Ret => format!("\nret"),
+  }
+}
+
+fn val_to_str(val: &Val) -> String {
+  match val {
+    Val::Imm(num) => format!("{}", *num),
+    Val::Reg(Reg::RAX) => format!("rax"),
+    Val::Reg(Reg::RBX) => format!("rbx"),
+    Val::Reg(Reg::RSP) => format!("rsp"),
+    Val::Reg(Reg::RDI) => format!("rdi"),
+    Val::Reg(Reg::RSI) => format!("rsi"),
+    Val::Reg(Reg::RCX) => format!("rcx"),
+    Val::Reg(Reg::RDX) => format!("rdx"),
+    Val::Reg(Reg::R8) => format!("r8"),
+    Val::Reg(Reg::R9) => format!("r9"),
+    Val::Reg(Reg::RTWELVE) => format!("r12"),
+    Val::Reg(Reg::RTHIRTEEN) => format!("r13"),
+    Val::Reg(Reg::RFOURTEEN) => format!("r14"),
+    Val::Reg(Reg::RFIFTHTEEN) => format!("r15"),
+    Val::RegOffset(Reg::RSP, offset) => format!("[rsp + {}]", offset),
+    Val::RegOnset(Reg::RSP, onset) => format!("[rsp - {}]", onset),
+    Val::RegSet(Reg::RFIFTHTEEN) => format!("[r15]"),
+    Val::RegSet(Reg::RCX) => format!("[rcx]"),
+    Val::RegSet(Reg::RTHIRTEEN) => format!("[r13]"),
+    Val::RegOffset(Reg::RFIFTHTEEN, offset) => format!("[r15 + {}]", offset),
+    Val::RegOffset(Reg::RAX, offset) => format!("[rax + {}]", offset),
+    Val::RegOnset(Reg::RAX, onset) => format!("[rax - {}]", onset),
+    Val::RegOffset(Reg::RBX, offset) => format!("[rbx + {}]", offset),
+    Val::RegOnset(Reg::RBX, onset) => format!("[rbx - {}]", onset),
+    _ => panic!("cannot convert val to str"),
+  }
+}
+
+// assembly label of a top-level function: fun_ and the name with every
+// character but letters and digits (as in point-x, point? or snek_gc) spelled
+// as a _xx hex code, so that distinct functions get distinct labels that
+// cannot clash with the runtime's symbols or the compiler's own labels
+fn fun_label(name: &str) -> String {
+  let mut st = String::from("fun_");
+  for c in name.chars() {
+    if c.is_ascii_alphanumeric() {
+      st.push(c);
+    } else {
+      st.push_str(&format!("_{:02x}", c as u32));
+    }
+  }
+  st
+}
+
+fn label_to_str(label: &Label) -> String {
+  match label {
+    Label::TYPEERROR => format!("TYPEERROR"),
+    Label::OVERFLOW => format!("OVERFLOW"),
+    Label::OUTBOUNDERROR => format!("OUTBOUNDERROR"),
+    Label::NILREF => format!("NILREF"),
+    Label::OUTOFMEMORY => format!("OUTOFMEMORY"),
+    Label::ARITYERROR => format!("ARITYERROR"),
+    Label::DIVZERO => format!("DIVZERO"),
+    Label::NOMATCH => format!("NOMATCH"),
+    Label::TUPLEARITY => format!("TUPLEARITY"),
+    Label::LName(st) => st.to_string(),
+    Label::Below(target, 0) => label_to_str(target),
+    Label::Below(target, n) => format!("{}_{}", label_to_str(target), n),
+  }
+}
+
+fn compile_expr(e: &Expr, v_args: &HashMap<String, usize>, func_table: &HashMap<String, usize>, cx: &Context, label: &mut i64, dep: usize, is_defn: bool, stubs: &mut Vec<String>) -> Result<String, CompileError> {
+  let mut s = String::new();
+  let v = compile_to_instrs(e, 0, 0, &HashMap::new(), v_args, func_table, cx, label, -1, dep, is_defn, is_defn)?;
+  error_stubs(&v, stubs);
+  for i in v {
+    s.push_str(&instr_to_str(&i));
+  }
+  Ok(s)
+}
+
+// A letrec function as seen from the code that calls it: its top-level name
+// and the extra arguments (captured variables) every call passes first.
+#[derive(Debug, Clone)]
+struct Lifted {
+  name: String,
+  extra: Vec<String>,
+  arity: usize,
+}
+
+// Lambda lifting: every letrec function becomes a top-level definition whose
+// first parameters are the local variables it uses, and calls to it pass those
+// variables along. Captured variables travel under an alias containing a space,
+// which user code cannot name and so cannot shadow. `scope` holds the local
+// variables in scope, `locals` the letrec functions in scope.
+fn lift(e: &Expr, scope: &im::HashSet<String>, locals: &HashMap<String, Lifted>, defs: &mut Vec<Statement>, func_table: &mut HashMap<String, usize>, n: &mut usize) -> Result<Expr, CompileError> {
+  Ok(match e {
+    Expr::Number(_) | Expr::Float(_) | Expr::Str(_) | Expr::NIL | Expr::TRUE | Expr::FALSE | Expr::INPUT => e.clone(),
+    Expr::Id(x) => {
+      match locals.get(x) {
+        // used as a value: wrap the call in a closure
+        Some(f) => {
+          let params: Vec<String> = (0..f.arity).map(|i| format!("arg {}", i)).collect();
+          let mut args: Vec<Expr> = f.extra.iter().map(|y| Expr::Id(y.to_string())).collect();
+          args.extend(params.iter().map(|y| Expr::Id(y.to_string())));
+          Expr::Lambda(params, Box::new(Expr::Funccall(f.name.to_string(), args)))
+        },
+        None => e.clone(),
+      }
+    },
+    Expr::Let(vec, body) => {
+      let mut nscope = scope.clone();
+      let mut nlocals = locals.clone();
+      let mut nvec = Vec::new();
+      for (p, e1) in vec {
+        nvec.push((p.clone(), lift(e1, &nscope, &nlocals, defs, func_table, n)?));
+        let mut names = im::HashSet::new();
+        pattern_vars(p, &mut names);
+        for x in names {
+          nlocals.remove(&x);
+          nscope.insert(x);
+        }
+      }
+      Expr::Let(nvec, Box::new(lift(body, &nscope, &nlocals, defs, func_table, n)?))
+    },
+    Expr::Match(e1, arms) => {
+      let ne1 = lift(e1, scope, locals, defs, func_table, n)?;
+      let mut narms = Vec::new();
+      for (p, body) in arms {
+        let mut nscope = scope.clone();
+        let mut nlocals = locals.clone();
+        let mut names = im::HashSet::new();
+        pattern_vars(p, &mut names);
+        for x in names {
+          nlocals.remove(&x);
+          nscope.insert(x);
+        }
+        narms.push((p.clone(), lift(body, &nscope, &nlocals, defs, func_table, n)?));
+      }
+      Expr::Match(Box::new(ne1), narms)
+    },
+    Expr::Try(body, x, handler) => {
+      let mut nscope = scope.clone();
+      let mut nlocals = locals.clone();
+      nlocals.remove(x);
+      nscope.insert(x.to_string());
+      Expr::Try(Box::new(lift(body, scope, locals, defs, func_table, n)?), x.to_string(), Box::new(lift(handler, &nscope, &nlocals, defs, func_table, n)?))
+    },
+    Expr::UnOp(op, e1) => Expr::UnOp(op.clone(), Box::new(lift(e1, scope, locals, defs, func_table, n)?)),
+    Expr::BinOp(op, e1, e2) => Expr::BinOp(op.clone(), Box::new(lift(e1, scope, locals, defs, func_table, n)?), Box::new(lift(e2, scope, locals, defs, func_table, n)?)),
+    Expr::Set(x, e1) => Expr::Set(x.to_string(), Box::new(lift(e1, scope, locals, defs, func_table, n)?)),
+    Expr::If(e1, e2, e3) => Expr::If(Box::new(lift(e1, scope, locals, defs, func_table, n)?), Box::new(lift(e2, scope, locals, defs, func_table, n)?), Box::new(lift(e3, scope, locals, defs, func_table, n)?)),
+    Expr::Block(vec) => Expr::Block(vec.iter().map(|e1| lift(e1, scope, locals, defs, func_table, n)).collect::<Result<_, _>>()?),
+    Expr::And(vec) => Expr::And(vec.iter().map(|e1| lift(e1, scope, locals, defs, func_table, n)).collect::<Result<_, _>>()?),
+    Expr::Or(vec) => Expr::Or(vec.iter().map(|e1| lift(e1, scope, locals, defs, func_table, n)).collect::<Result<_, _>>()?),
+    Expr::Tuple(vec) => Expr::Tuple(vec.iter().map(|e1| lift(e1, scope, locals, defs, func_table, n)).collect::<Result<_, _>>()?),
+    Expr::Struct(id, vec) => Expr::Struct(*id, vec.iter().map(|e1| lift(e1, scope, locals, defs, func_table, n)).collect::<Result<_, _>>()?),
+    Expr::Loop(e1) => Expr::Loop(Box::new(lift(e1, scope, locals, defs, func_table, n)?)),
+    Expr::Break(e1) => Expr::Break(Box::new(lift(e1, scope, locals, defs, func_table, n)?)),
+    Expr::Index(e1, e2) => Expr::Index(Box::new(lift(e1, scope, locals, defs, func_table, n)?), Box::new(lift(e2, scope, locals, defs, func_table, n)?)),
+    Expr::SetIndex(e1, e2, e3) => Expr::SetIndex(Box::new(lift(e1, scope, locals, defs, func_table, n)?), Box::new(lift(e2, scope, locals, defs, func_table, n)?), Box::new(lift(e3, scope, locals, defs, func_table, n)?)),
+    Expr::Funccall(name, vec) => {
+      let args: Vec<Expr> = vec.iter().map(|e1| lift(e1, scope, locals, defs, func_table, n)).collect::<Result<_, _>>()?;
+      match locals.get(name) {
+        Some(f) => {
+          let mut nargs: Vec<Expr> = f.extra.iter().map(|y| Expr::Id(y.to_string())).collect();
+          nargs.extend(args);
+          Expr::Funccall(f.name.to_string(), nargs)
+        },
+        None => Expr::Funccall(name.to_string(), args),
+      }
+    },
+    Expr::Lambda(params, body) => {
+      let mut nscope = scope.clone();
+      let mut nlocals = locals.clone();
+      for p in params {
+        nlocals.remove(p);
+        nscope.insert(p.to_string());
+      }
+      Expr::Lambda(params.clone(), Box::new(lift(body, &nscope, &nlocals, defs, func_table, n)?))
+    },
+    Expr::Call(f, vec) => Expr::Call(Box::new(lift(f, scope, locals, defs, func_table, n)?), vec.iter().map(|e1| lift(e1, scope, locals, defs, func_table, n)).collect::<Result<_, _>>()?),
+    Expr::At(span, e1) => Expr::At(*span, Box::new(lift(e1, scope, locals, defs, func_table, n).map_err(|err| err.at(*span))?)),
+    Expr::Letrec(funs, body) => {
+      let id = *n;
+      *n += 1;
+      let mut group = im::HashSet::new();
+      for (f, _, _) in funs {
+        if group.contains(f) {
+          return Err(CompileError::DuplicateFunction(f.to_string()));
+        }
+        group.insert(f.to_string());
+      }
+      // variables of the enclosing scope used by any function of the group,
+      // including the ones passed along to enclosing letrec functions
+      let mut fv = Vec::new();
+      for (_, params, fbody) in funs {
+        let mut bound = group.clone();
+        bound.extend(params.iter().cloned());
+        free_vars(fbody, &bound, &mut fv);
+      }
+      let mut captured = Vec::<String>::new();
+      for x in &fv {
+        let xs = match locals.get(x) {
+          Some(f) => f.extra.clone(),
+          None if scope.contains(x) => vec![x.to_string()],
+          None => Vec::new(),
+        };
+        for y in xs {
+          if !captured.contains(&y) {
+            captured.push(y);
+          }
+        }
+      }
+      let alias = |x: &String| if x.contains(' ') { x.to_string() } else { format!("{} {}", x, id) };
+      let extra: Vec<String> = captured.iter().map(alias).collect();
+
+      let mut nlocals = locals.clone();
+      for (f, params, _) in funs {
+        let mut name = format!("{}_letrec{}", f, id);
+        while func_table.contains_key(&name) {
+          name.push('_');
+        }
+        func_table.insert(name.to_string(), extra.len() + params.len());
+        nlocals.insert(f.to_string(), Lifted { name, extra: extra.clone(), arity: params.len() });
+      }
+      let mut nscope = scope.clone();
+      nscope.extend(extra.iter().cloned());
+      // the captured user variables are bound again from their aliases
+      let rebind = |fbody: Expr, bound: &im::HashSet<String>| -> Expr {
+        let mut used = Vec::new();
+        free_vars(&fbody, bound, &mut used);
+        let binds: Vec<(Pattern, Expr)> = captured.iter().zip(extra.iter())
+          .filter(|(x, y)| x != y && used.contains(x))
+          .map(|(x, y)| (Pattern::Var(x.to_string()), Expr::Id(y.to_string())))
+          .collect();
+        if binds.is_empty() { fbody } else { Expr::Let(binds, Box::new(fbody)) }
+      };
+      for (f, params, fbody) in funs {
+        let mut fscope: im::HashSet<String> = extra.iter().cloned().collect();
+        let mut flocals = nlocals.clone();
+        for x in &captured {
+          if !params.contains(x) {
+            fscope.insert(x.to_string());
+          }
+        }
+        for p in params {
+          flocals.remove(p);
+          fscope.insert(p.to_string());
+        }
+        let span = if let Expr::At(span, _) = fbody { *span } else { Span::default() };
+        let nbody = lift(fbody, &fscope, &flocals, defs, func_table, n)?;
+        let mut bound = group.clone();
+        bound.extend(params.iter().cloned());
+        let nbody = rebind(nbody, &bound);
+        let mut names = vec![nlocals.get(f).unwrap().name.to_string()];
+        names.extend(extra.iter().cloned());
+        names.extend(params.iter().cloned());
+        defs.push(Statement::Definition(names, Box::new(nbody), span));
+      }
+      // and the aliases are bound from the captured variables before the body
+      let nbody = lift(body, &nscope, &nlocals, defs, func_table, n)?;
+      let binds: Vec<(Pattern, Expr)> = captured.iter().zip(extra.iter())
+        .filter(|(x, y)| x != y)
+        .map(|(x, y)| (Pattern::Var(y.to_string()), Expr::Id(x.to_string())))
+        .collect();
+      if binds.is_empty() { nbody } else { Expr::Let(binds, Box::new(nbody)) }
+    },
+  })
+}
+
+fn lift_prog(prog: Vec<Statement>, func_table: &mut HashMap<String, usize>) -> Result<Vec<Statement>, CompileError> {
+  let mut defs = Vec::new();
+  let mut n = 0;
+  let mut v = Vec::new();
+  for stmt in prog {
+    match stmt {
+      Statement::Definition(names, body, span) => {
+        let scope = names[1..].iter().cloned().collect();
+        let nbody = lift(&body, &scope, &HashMap::new(), &mut defs, func_table, &mut n)?;
+        v.push(Statement::Definition(names, Box::new(nbody), span));
+      },
+      Statement::Expression(body, span) => {
+        let nbody = lift(&body, &im::HashSet::new(), &HashMap::new(), &mut defs, func_table, &mut n)?;
+        v.append(&mut defs);
+        v.push(Statement::Expression(Box::new(nbody), span));
+      },
+    }
+  }
+  Ok(v)
+}
+
+// names used in e that are not bound inside it, in order of first use
+fn free_vars(e: &Expr, bound: &im::HashSet<String>, fv: &mut Vec<String>) {
+  match e {
+    Expr::Number(_) | Expr::Float(_) | Expr::Str(_) | Expr::NIL | Expr::TRUE | Expr::FALSE | Expr::INPUT => {},
+    Expr::Id(s) => {
+      if !bound.contains(s) && !fv.contains(s) {
+        fv.push(s.to_string());
+      }
+    },
+    Expr::Let(vec, body) => {
+      let mut nbound = bound.clone();
+      for (p, e) in vec {
+        free_vars(e, &nbound, fv);
+        pattern_vars(p, &mut nbound);
+      }
+      free_vars(body, &nbound, fv);
+    },
+    Expr::UnOp(_, e1) | Expr::Loop(e1) | Expr::Break(e1) => free_vars(e1, bound, fv),
+    Expr::Match(e1, arms) => {
+      free_vars(e1, bound, fv);
+      for (p, body) in arms {
+        let mut nbound = bound.clone();
+        pattern_vars(p, &mut nbound);
+        free_vars(body, &nbound, fv);
+      }
+    },
+    Expr::Try(body, x, handler) => {
+      free_vars(body, bound, fv);
+      free_vars(handler, &bound.update(x.to_string()), fv);
+    },
+    Expr::BinOp(_, e1, e2) | Expr::Index(e1, e2) => {
+      free_vars(e1, bound, fv);
+      free_vars(e2, bound, fv);
+    },
+    Expr::Set(s, e1) => {
+      if !bound.contains(s) && !fv.contains(s) {
+        fv.push(s.to_string());
+      }
+      free_vars(e1, bound, fv);
+    },
+    Expr::If(e1, e2, e3) | Expr::SetIndex(e1, e2, e3) => {
+      free_vars(e1, bound, fv);
+      free_vars(e2, bound, fv);
+      free_vars(e3, bound, fv);
+    },
+    Expr::Block(vec) | Expr::Tuple(vec) | Expr::Struct(_, vec) | Expr::And(vec) | Expr::Or(vec) => {
+      for e in vec {
+        free_vars(e, bound, fv);
+      }
+    },
+    Expr::Funccall(name, vec) => {
+      if !bound.contains(name) && !fv.contains(name) {
+        fv.push(name.to_string());
+      }
+      for e in vec {
+        free_vars(e, bound, fv);
+      }
+    },
+    Expr::Lambda(params, body) => {
+      let mut nbound = bound.clone();
+      for p in params {
+        nbound.insert(p.to_string());
+      }
+      free_vars(body, &nbound, fv);
+    },
+    Expr::Call(f, vec) => {
+      free_vars(f, bound, fv);
+      for e in vec {
+        free_vars(e, bound, fv);
+      }
+    },
+    Expr::At(_, e1) => free_vars(e1, bound, fv),
+    Expr::Letrec(funs, body) => {
+      let mut nbound = bound.clone();
+      for (f, _, _) in funs {
+        nbound.insert(f.to_string());
+      }
+      for (_, params, fbody) in funs {
+        let mut fbound = nbound.clone();
+        for p in params {
+          fbound.insert(p.to_string());
+        }
+        free_vars(fbody, &fbound, fv);
+      }
+      free_vars(body, &nbound, fv);
+    },
+  }
+}
+
+fn pattern_vars(p: &Pattern, bound: &mut im::HashSet<String>) {
+  match p {
+    Pattern::Var(x) => {
+      bound.insert(x.to_string());
+    },
+    Pattern::Tuple(ps) | Pattern::Struct(_, ps) => {
+      for sub in ps {
+        pattern_vars(sub, bound);
+      }
+    },
+    _ => {},
+  }
+}
+
+// stack slots taken by a let binding
+fn bind_slots(p: &Pattern) -> usize {
+  match p {
+    Pattern::Tuple(ps) => ps.iter().filter(|x| !matches!(x, Pattern::Wildcard)).count(),
+    _ => 1,
+  }
+}
+
+// stack slots compile_pattern uses below the matched value
+fn pattern_slots(p: &Pattern) -> usize {
+  match p {
+    Pattern::Tuple(ps) | Pattern::Struct(_, ps) => ps.iter().map(|sub| match sub {
+      Pattern::Wildcard => 0,
+      _ => 1 + pattern_slots(sub),
+    }).sum(),
+    _ => 0,
+  }
+}
+
+fn depth(e: &Expr) -> usize {
+  match e {
+    Expr::Number(_) | Expr::Float(_) | Expr::Str(_) | Expr::NIL | Expr::TRUE | Expr::FALSE | Expr::INPUT | Expr::Id(_)=> 0,
+    Expr::Let(vec, e1) => {
+      let mut ma = 0;
+      let mut used = 0;
+      for (p, e) in vec {
+        ma = ma.max(depth(e) + used);
+        used += bind_slots(p);
+      }
+      ma = ma.max(depth(e1) + used);
+      ma
+    },
+    Expr::UnOp(op, e1) => depth(e1),
+    Expr::BinOp(op, e1, e2) => (depth(e1) + 1).max(depth(e2)),
+    Expr::Set(id, e1) => depth(e1),
+    Expr::If(e1, e2, e3) => depth(e1).max(depth(e2).max(depth(e3))),
+    Expr::Block(vec) | Expr::And(vec) | Expr::Or(vec) => {
+      let mut ma = 0;
+      for e in vec {
+        ma = ma.max(depth(e));
+      }
+      ma
+    },
+    Expr::Loop(e1) => depth(e1),
+    Expr::At(_, e1) => depth(e1),
+    // lifted away before depth is needed
+    Expr::Letrec(_, e1) => depth(e1),
+    Expr::Match(e1, arms) => {
+      let mut ma = depth(e1);
+      for (p, body) in arms {
+        ma = ma.max(1 + pattern_slots(p) + depth(body));
+      }
+      ma
+    },
+    Expr::Try(body, _, handler) => (6 + depth(body)).max(1 + depth(handler)),
+    Expr::Break(e1) => depth(e1),
+    Expr::Tuple(vec) | Expr::Struct(_, vec) => {
+      let mut ma = 0;
+      for e in vec {
+        ma = ma.max(depth(e));
+      }
+      ma
+    },
+    Expr::Index(e1, e2) => (depth(e1) + 1).max(depth(e2)),
+    Expr::SetIndex(e1, e2, e3) => (depth(e1) + 2).max(depth(e2) + 1).max(depth(e3)),
+    Expr::Funccall(id, vec) => {
+      let mut ma = 0;
+      for e in vec {
+        ma = ma.max(depth(e));
+      }
+      ma
+    },
+    Expr::Lambda(_, _) => 0, // the body gets its own frame
+    Expr::Call(f, vec) => {
+      let mut ma = depth(f);
+      for e in vec {
+        ma = ma.max(depth(e));
+      }
+      ma
+    },
+  }
+}
+
+// the most parameters a lambda in e takes, or arguments a call in e passes
+// (calls of function values can pass more than any lambda takes)
+fn most_args(e: &Expr) -> usize {
+  let most = |es: &[Expr]| es.iter().map(most_args).max().unwrap_or(0);
+  match e {
+    Expr::Number(_) | Expr::Float(_) | Expr::Str(_) | Expr::NIL | Expr::TRUE | Expr::FALSE | Expr::INPUT | Expr::Id(_) => 0,
+    Expr::Let(vec, e1) => vec.iter().map(|(_, e)| most_args(e)).max().unwrap_or(0).max(most_args(e1)),
+    Expr::UnOp(_, e1) | Expr::Set(_, e1) | Expr::Loop(e1) | Expr::Break(e1) | Expr::At(_, e1) => most_args(e1),
+    Expr::BinOp(_, e1, e2) | Expr::Index(e1, e2) | Expr::Try(e1, _, e2) => most_args(e1).max(most_args(e2)),
+    Expr::If(e1, e2, e3) | Expr::SetIndex(e1, e2, e3) => most_args(e1).max(most_args(e2)).max(most_args(e3)),
+    Expr::Block(vec) | Expr::And(vec) | Expr::Or(vec) | Expr::Tuple(vec) | Expr::Struct(_, vec) => most(vec),
+    Expr::Lambda(params, body) => params.len().max(most_args(body)),
+    Expr::Funccall(_, vec) => vec.len().max(most(vec)),
+    Expr::Call(f, vec) => vec.len().max(most_args(f)).max(most(vec)),
+    Expr::Match(e1, arms) => arms.iter().map(|(_, body)| most_args(body)).max().unwrap_or(0).max(most_args(e1)),
+    Expr::Letrec(defs, e1) => defs.iter().map(|(_, params, body)| params.len().max(most_args(body))).max().unwrap_or(0).max(most_args(e1)),
+  }
+}
+
+// The error message, then the file, line and column of its location and the
+// source line with the located part underlined:
+//
+//   error: unbound variable identifier y
+//    --> prog.snek:1:19
+//     |
+//   1 | (let ((x 1)) (+ x y))
+//     |                   ^
+pub fn render_error(err: &CompileError, file: &str, src: &str) -> String {
+  let (span, msg) = match err {
+    CompileError::At(span, msg) => (*span, msg),
+    _ => return format!("error: {}", err),
+  };
+  let line_start = src[..span.start].rfind('\n').map_or(0, |i| i + 1);
+  let line_end = src[span.start..].find('\n').map_or(src.len(), |i| span.start + i);
+  let line = src[..span.start].matches('\n').count() + 1;
+  let col = src[line_start..span.start].chars().count() + 1;
+  // keep tabs so that the carets line up with the source
+  let pad: String = src[line_start..span.start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+  let width = src[span.start..span.end.min(line_end)].chars().count().max(1);
+  let gutter = " ".repeat(line.to_string().len());
+  format!(
+    "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+    msg, gutter, file, line, col, gutter, line, src[line_start..line_end].trim_end_matches('\r'), gutter, pad, "^".repeat(width)
+  )
+}
+
+// A parsed program: its definitions, ending with the main expression, the
+// arity of every top-level function and the constructors of its struct types.
+#[derive(Debug, Clone)]
+pub struct Program {
+  statements: Vec<Statement>,
+  func_table: HashMap<String, usize>,
+  constructors: Vec<Constructor>,
+}
+
+impl Program {
+  // The constructors of the struct types the program defines, with the number
+  // of fields each takes, in the order they are defined.
+  pub fn constructors(&self) -> impl Iterator<Item = (&str, usize)> {
+    self.constructors.iter().map(|c| (c.name.as_str(), c.size))
+  }
+}
+
+// How compile_to_asm generates code.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+  // arithmetic that overflows promotes to bignums instead of raising an error
+  pub bignum: bool,
+}
+
+// What the code of every expression depends on besides the expression itself.
+struct Context {
+  opts: Options,
+  // the argument slots every call reserves: the most parameters a function or
+  // lambda of the program takes, so that a call in tail position always fits
+  // in the slots of the current function
+  slots: usize,
+}
+
+fn sort_errors(errors: &mut [CompileError]) {
+  errors.sort_by_key(|e| if let CompileError::At(span, _) = e { span.start } else { 0 });
+}
+
+// Reads and parses a program, returning the errors of all its invalid
+// top-level forms in the order they appear in the source.
+pub fn parse(src: &str) -> Result<Program, Vec<CompileError>> {
+  let s_expr = read_prog(src)?;
+  let mut func_table = HashMap::new();
+  let mut constructors = Vec::new();
+  let (statements, mut errors) = parse_prog(&s_expr, &mut func_table, &mut constructors);
+  if !errors.is_empty() {
+    sort_errors(&mut errors);
+    return Err(errors);
+  }
+  Ok(Program { statements, func_table, constructors })
+}
+
+// Finds every error compile_to_asm would stop at, in source order.
+pub fn check(prog: &Program) -> Result<(), Vec<CompileError>> {
+  let mut errors = check_prog(&prog.statements, &prog.func_table);
+  if !errors.is_empty() {
+    sort_errors(&mut errors);
+    return Err(errors);
+  }
+  Ok(())
+}
+
+// The assembly of a checked program, to be linked with the runtime.
+pub fn compile_to_asm(prog: &Program, opts: Options) -> Result<String, CompileError> {
+  compile_prog(prog.statements.clone(), prog.func_table.clone(), opts)
+}
+
+// All the phases, as the compiler binary runs them. The definitions that do
+// parse are checked even when others do not, so that the errors of parsing and
+// checking are all reported together.
+pub fn compile(src: &str, opts: Options) -> Result<String, Vec<CompileError>> {
+  let s_expr = read_prog(src)?;
+  let mut func_table = HashMap::new();
+  let mut constructors = Vec::new();
+  let (statements, mut errors) = parse_prog(&s_expr, &mut func_table, &mut constructors);
+  errors.extend(check_prog(&statements, &func_table));
+  if !errors.is_empty() {
+    sort_errors(&mut errors);
+    return Err(errors);
+  }
+  compile_to_asm(&Program { statements, func_table, constructors }, opts).map_err(|e| vec![e])
+}
+
+// lambda lifting and code generation, for a program that passed check_prog
+fn compile_prog(v_prog: Vec<Statement>, mut func_table: HashMap<String, usize>, opts: Options) -> Result<String, CompileError> {
+    let v_prog = lift_prog(v_prog, &mut func_table)?;
+    let mut slots = func_table.values().copied().max().unwrap_or(0);
+    for stmt in &v_prog {
+      match stmt {
+        Statement::Definition(_, e, _) | Statement::Expression(e, _) => slots = slots.max(most_args(e)),
+      }
+    }
+    let cx = Context { opts, slots };
+    // let expr = parse_expr(&s_expr);
+
+    let mut result = String::new();
+    let mut label = 0;
+    let mut stubs = Vec::new();
+    if let Some((expr, defns)) = v_prog.split_last() {
+      for defn in defns {
+        match &defn {
+          Statement::Definition(names, expr, span) => {
+            if let Some((func_name, args)) = names.split_first() {
+              result.push_str(&format!("\n{}:", fun_label(func_name)));
+              let mut dep = depth(expr) + 2;
+              if dep % 2 != 0 {
+                dep += 1;
+              }
+              result.push_str(&format!("\n  sub rsp, {}", dep * 8));
+              let mut v_args = HashMap::new();
+              for (idx, arg) in args.iter().enumerate() {
+                check_name(arg).map_err(|e| e.at(*span))?;
+                if v_args.contains_key(arg) {
+                  return Err(CompileError::DuplicateBinding(arg.to_string()).at(*span));
+                }
+                v_args.insert(arg.to_string(), idx);
+              }
+              result.push_str(&compile_expr(expr, &v_args, &func_table, &cx, &mut label, dep, true, &mut stubs).map_err(|e| e.at(*span))?);
+              result.push_str(&format!("\n  add rsp, {}", dep * 8));
+              result.push_str(&format!("\n  ret"));
+            }
+          },
+          _ => return Err(CompileError::Internal("main expression before the last definition".to_string())),
+        }
+      }
+      match &expr {
+        Statement::Expression(e, span) => {
+          let mut dep = depth(e) + 2;
+          if dep % 2 != 0 {
+            dep += 1;
+          }
+          result.push_str(&format!("\nour_code_starts_here:"));
+          result.push_str(&format!("\npush rbx"));
+          result.push_str(&format!("\npush r12"));
+          result.push_str(&format!("\npush r14"));
+          result.push_str(&format!("\npush r15"));
+          result.push_str(&format!("\npush r13"));
+          result.push_str(&format!("\nsub rsp, 8")); // keep the stack alignment of a function
+          result.push_str(&format!("\nmov r12, rsp")); // stack base for the gc
+          result.push_str(&format!("\nsub rsp, {}", dep * 8));
+          result.push_str(&format!("\nmov r15, rsi"));
+          result.push_str(&format!("\nmov r14, rdx"));
+          result.push_str(&format!("\nmov r13, rcx")); // where the innermost try handler is kept
+          result.push_str(&compile_expr(e, &HashMap::new(), &func_table, &cx, &mut label, dep, false, &mut stubs).map_err(|e| e.at(*span))?);
+          result.push_str(&format!("\nadd rsp, {}", dep * 8));
+          result.push_str(&format!("\nadd rsp, 8"));
+          result.push_str(&format!("\npop r13"));
+          result.push_str(&format!("\npop r15"));
+          result.push_str(&format!("\npop r14"));
+          result.push_str(&format!("\npop r12"));
+          result.push_str(&format!("\npop rbx"));
+          result.push_str(&format!("\n  ret"));
+        },
+        _ => return Err(CompileError::Internal("no main expression".to_string())),
+      }
+    }
+    result.push_str(&stubs.concat());
+
+    let asm_program = format!(
+        "
+section .text
+extern snek_error
+extern snek_print
+extern snek_gc
+extern snek_equal
+extern snek_string_append
+extern snek_string_ref
+extern snek_num_op
+extern snek_struct_info
+extern snek_raise
+global our_code_starts_here
+global snek_throw
+  {}
+snek_throw:
+  mov rax, rsi
+  mov r15, rdx
+  mov rsp, [rdi + 8]
+  jmp qword [rdi + 16]
+TYPEERROR:
+  mov rdi, 1
+  mov rdx, r15
+  and rsp, -16
+  call snek_error
+OVERFLOW:
+  mov rdi, 2
+  mov rdx, r15
+  and rsp, -16
+  call snek_error
+OUTBOUNDERROR:
+  mov rdi, 3
+  mov rdx, r15
+  and rsp, -16
+  call snek_error
+NILREF:
+  mov rdi, 4
+  mov rdx, r15
+  and rsp, -16
+  call snek_error
+OUTOFMEMORY:
+  mov rdi, 5
+  mov rdx, r15
+  and rsp, -16
+  call snek_error
+ARITYERROR:
+  mov rdi, 6
+  mov rdx, r15
+  and rsp, -16
+  call snek_error
+DIVZERO:
+  mov rdi, 7
+  mov rdx, r15
+  and rsp, -16
+  call snek_error
+NOMATCH:
+  mov rdi, 8
+  mov rdx, r15
+  and rsp, -16
+  call snek_error
+TUPLEARITY:
+  mov rdi, 9
+  mov rdx, r15
+  and rsp, -16
+  call snek_error
+",
+        result
+    );
+    Ok(asm_program)
+}
@@ -29,6 +29,194 @@ success_tests! {
         file: "even_odd.snek",
         input: "9",
         expected: "9\nfalse\nfalse",
+    },
+    {
+        name: gc_loop,
+        file: "gc_loop.snek",
+        expected: "(tuple 99999 100000)",
+    },
+    {
+        name: gc_live,
+        file: "gc_live.snek",
+        input: "50000",
+        expected: "50000\n500500",
+    },
+    {
+        name: gc_bst,
+        file: "gc_bst.snek",
+        expected: "1501",
+    },
+    {
+        name: setindex,
+        file: "setindex.snek",
+        expected: "(tuple 4 5)\n(tuple 1 (tuple 4 5) 3)\n3",
+    },
+    {
+        name: equal,
+        file: "equal.snek",
+        expected: "false\ntrue\nfalse\ntrue\nfalse\ntrue\nfalse\ntrue",
+    },
+    {
+        name: print_cycle,
+        file: "print_cycle.snek",
+        expected: "(tuple 1 (tuple 2 <cyclic tuple>))\n(tuple (tuple 3) (tuple 3))\n(tuple <cyclic tuple>)",
+    },
+    {
+        name: lambda,
+        file: "lambda.snek",
+        expected: "(tuple 11 (tuple 12 (tuple 13 nil)))\n6\n(tuple 2 (tuple 4 (tuple 6 nil)))\n105\n10\ntrue\n<function>\n10",
+    },
+    {
+        name: lambda_gc,
+        file: "lambda_gc.snek",
+        expected: "5000",
+    },
+    {
+        name: even_odd_deep,
+        file: "even_odd.snek",
+        input: "1000001",
+        expected: "1000001\nfalse\nfalse",
+    },
+    {
+        name: tail_sum,
+        file: "tail_sum.snek",
+        input: "1000000",
+        expected: "13\n500000500000",
+    },
+    {
+        name: tail_mixed_arity,
+        file: "tail_mixed_arity.snek",
+        input: "3000000",
+        expected: "1\n0",
+    },
+    {
+        name: tail_lambda,
+        file: "tail_lambda.snek",
+        input: "1000000",
+        expected: "true",
+    },
+    {
+        name: strings,
+        file: "strings.snek",
+        expected: "hello, world\n12\nh\ntrue\nfalse\n(tuple \"a\\\"b\" 1)\n",
+    },
+    {
+        name: string_gc,
+        file: "string_gc.snek",
+        input: "100000",
+        expected: "11",
+    },
+    {
+        name: divide,
+        file: "divide.snek",
+        expected: "3\n-3\n2\n-2\n-4611686018427387903\n1",
+    },
+    {
+        name: bool_ops,
+        file: "bool_ops.snek",
+        input: "4",
+        expected: "false\nfalse\ntrue\ntrue\ntrue\nfalse\ntrue\ntrue",
+    },
+    {
+        name: bst_match,
+        file: "bst_match.snek",
+        expected: "(tuple 4 (tuple 2 (tuple 1 (tuple 0 nil nil) nil) (tuple 3 nil nil)) (tuple 6 (tuple 5 nil nil) (tuple 7 nil nil)))\ntrue\nzero\ntrue\n5\n(tuple 2 1)\nother\nother\nfalse",
+    },
+    {
+        name: let_tuple,
+        file: "let_tuple.snek",
+        expected: "(tuple 11 23)\n34\n3",
+    },
+    {
+        name: letrec,
+        file: "letrec.snek",
+        input: "100000",
+        expected: "true\n2\n11\n20\n15\n20",
+    },
+    {
+        name: inspect,
+        file: "inspect.snek",
+        expected: "3\n2\n0\n-1\n(tuple true false false false false)\n(tuple true false false)\n(tuple true false false false)\n(tuple 0 1 2 3 4 5)",
+    },
+    {
+        name: make_vec,
+        file: "make_vec.snek",
+        input: "5",
+        expected: "(tuple)\n(tuple (tuple 1 2) (tuple 1 2) (tuple 1 2))\n(tuple 9 2)\n25\n5",
+    },
+    {
+        name: make_vec_gc,
+        file: "make_vec_gc.snek",
+        input: "1000",
+        expected: "1",
+    },
+    {
+        name: bignum_fact,
+        file: "bignum_fact.snek",
+        input: "30",
+        flags: ["--bignum"],
+        expected: "2432902008176640000\n51090942171709440000\n30\n790627\n-15511210043330985984000000\ntrue\ntrue\ntrue\ntrue\n0\n0\n4611686018427387904\n-4611686018427387905\n265252859812191058636308480000000",
+    },
+    {
+        name: bignum_gc,
+        file: "bignum_gc.snek",
+        input: "3000",
+        flags: ["--bignum"],
+        expected: "7",
+    },
+    {
+        name: floats,
+        file: "floats.snek",
+        input: "4",
+        expected: "5.5\n-3.0\n0.25\n1.5\ntrue\ntrue\n(tuple true false false 7)\n-2\n(tuple 0.1 1e20)\n42",
+    },
+    {
+        name: float_input,
+        file: "float_input.snek",
+        input: "2.5",
+        expected: "true\n5.0",
+    },
+    {
+        name: struct_points,
+        file: "struct_points.snek",
+        input: "5",
+        expected: "(point x: 6 y: 12)\n(line from: (point x: 1 y: 2) to: (point x: \"a\" y: nil))\n(tuple true false false false 8)\ntrue\nfalse\n2\n10",
+    },
+    {
+        name: function_names,
+        file: "function_names.snek",
+        input: "100000",
+        expected: "(tuple 1 2 10 2)\n2",
+    },
+    {
+        name: data_shapes,
+        file: "data_shapes.snek",
+        input: "4",
+        expected: "(tuple 12 12 25)\n(rect w: 3 h: 4)\n(tuple true false false true true)\n(node left: (node left: (leaf) val: 1 right: (leaf)) val: 2 right: (leaf))\n2\n17",
+    },
+    {
+        name: exceptions,
+        file: "exceptions.snek",
+        input: "2",
+        expected: "5\n(tuple \"error\" 7)\n(tuple \"not found\" 5)\n2\n(tuple \"bottom\" 1)\n1\n3\n20\n101\nbottom!",
+    },
+    {
+        name: break_in_try,
+        file: "break_in_try.snek",
+        input: "0",
+        expected: "1\n1\n2\n30\nafter",
+    },
+    {
+        name: exception_temporaries,
+        file: "exception_temporaries.snek",
+        input: "42",
+        expected: "(tuple 5 7)\n(tuple (tuple 1 2) 3 (tuple 1 2))\n1\n(tuple 7 42)",
+    },
+    {
+        name: exception_gc,
+        file: "exception_gc.snek",
+        input: "100000",
+        expected: "(tuple 33334 99999)",
     }
 }
 
@@ -37,6 +225,152 @@ runtime_error_tests! {
         name: type_error,
         file: "type_error.snek",
         expected: "invalid argument",
+    },
+    {
+        name: oom,
+        file: "oom.snek",
+        expected: "out of memory",
+    },
+    {
+        name: setindex_bounds,
+        file: "setindex_bounds.snek",
+        expected: "index out of bound, 4",
+    },
+    {
+        name: setindex_nil,
+        file: "setindex_nil.snek",
+        expected: "try to index of nil",
+    },
+    {
+        name: lambda_arity,
+        file: "lambda_arity.snek",
+        expected: "wrong number of arguments",
+    },
+    {
+        name: lambda_not_fn,
+        file: "lambda_not_fn.snek",
+        expected: "invalid argument",
+    },
+    {
+        name: string_ref_bounds,
+        file: "string_ref_bounds.snek",
+        expected: "index out of bound, 4",
+    },
+    {
+        name: string_length_num,
+        file: "string_length_num.snek",
+        expected: "invalid argument",
+    },
+    {
+        name: divide_zero,
+        file: "divide_zero.snek",
+        input: "3",
+        expected: "division by zero",
+    },
+    {
+        name: divide_overflow,
+        file: "divide_overflow.snek",
+        expected: "overflow",
+    },
+    {
+        name: mod_bool,
+        file: "mod_bool.snek",
+        expected: "invalid argument",
+    },
+    {
+        name: and_num,
+        file: "and_num.snek",
+        input: "1",
+        expected: "invalid argument",
+    },
+    {
+        name: not_num,
+        file: "not_num.snek",
+        expected: "invalid argument",
+    },
+    {
+        name: match_fail,
+        file: "match_fail.snek",
+        expected: "no pattern matched",
+    },
+    {
+        name: let_tuple_arity,
+        file: "let_tuple_arity.snek",
+        expected: "tuple arity mismatch",
+    },
+    {
+        name: let_tuple_num,
+        file: "let_tuple_num.snek",
+        input: "5",
+        expected: "invalid argument",
+    },
+    {
+        name: len_num,
+        file: "len_num.snek",
+        input: "3",
+        expected: "invalid argument",
+    },
+    {
+        name: make_vec_neg,
+        file: "make_vec_neg.snek",
+        input: "2",
+        expected: "invalid argument",
+    },
+    {
+        name: make_vec_oom,
+        file: "make_vec_oom.snek",
+        input: "200000",
+        expected: "out of memory",
+    },
+    {
+        name: bignum_type,
+        file: "bignum_type.snek",
+        flags: ["--bignum"],
+        expected: "invalid argument",
+    },
+    {
+        name: bignum_off_overflow,
+        file: "fact.snek",
+        input: "21",
+        expected: "overflow",
+    },
+    {
+        name: float_input_too_big,
+        file: "float_input.snek",
+        input: "99999999999999999999",
+        expected: "Invalid",
+    },
+    {
+        name: float_input_inf,
+        file: "float_input.snek",
+        input: "inf",
+        expected: "Invalid",
+    },
+    {
+        name: float_mix,
+        file: "float_mix.snek",
+        input: "1",
+        expected: "invalid argument",
+    },
+    {
+        name: float_to_int_overflow,
+        file: "float_to_int_overflow.snek",
+        expected: "overflow",
+    },
+    {
+        name: struct_wrong_type,
+        file: "struct_wrong_type.snek",
+        expected: "invalid argument",
+    },
+    {
+        name: data_no_match,
+        file: "data_no_match.snek",
+        expected: "no pattern matched",
+    },
+    {
+        name: raise_uncaught,
+        file: "raise_uncaught.snek",
+        expected: "uncaught exception: (tuple \"oops\" 2)",
     }
 }
 
@@ -44,6 +378,71 @@ static_error_tests! {
     {
         name: duplicate_params,
         file: "duplicate_params.snek",
-        expected: "",
+        expected: "duplicate binding a",
+    },
+    {
+        name: data_bad_pattern,
+        file: "data_bad_pattern.snek",
+        expected: "wrong number of arguments to rect: expected 2, found 1",
+    },
+    {
+        name: unbound_var,
+        file: "unbound_var.snek",
+        expected: "unbound variable identifier y",
+    },
+    {
+        name: break_outside,
+        file: "break_outside.snek",
+        expected: "break outside of a loop",
+    },
+    {
+        name: lambda_set_captured,
+        file: "lambda_set_captured.snek",
+        expected: "cannot assign captured variable c",
+    },
+    {
+        name: letrec_set_captured,
+        file: "letrec_set_captured.snek",
+        expected: "cannot assign captured variable c",
+    },
+    {
+        name: literal_range,
+        file: "literal_range.snek",
+        expected: "literal out of range: 4611686018427387904",
+    },
+    {
+        name: arity_mismatch,
+        file: "arity_mismatch.snek",
+        expected: "wrong number of arguments to f: expected 1, found 2",
+    },
+    {
+        name: keyword_binding,
+        file: "keyword_binding.snek",
+        expected: "keyword if used as a name",
+    },
+    {
+        name: keyword_fun_name,
+        file: "keyword_fun_name.snek",
+        expected: "keyword tuple used as a name",
+    },
+    {
+        name: keyword_builtin,
+        file: "keyword_builtin.snek",
+        expected: "keyword and used as a name",
+    },
+    {
+        name: unbound_in_fun,
+        file: "unbound_in_fun.snek",
+        expected: "unbound_in_fun.snek:5:12\n  |\n5 |   (* (f y) z))\n  |            ^",
+    },
+    {
+        name: unclosed_paren,
+        file: "unclosed_paren.snek",
+        expected: "unclosed parenthesis\n --> tests/unclosed_paren.snek:1:1",
+    },
+    {
+        name: many_errors,
+        file: "many_errors.snek",
+        expected: "error: unbound variable identifier z\n  --> tests/many_errors.snek:15:3\n   |\n15 |   (set! z 3)\n   |   ^^^^^^^^^^\n\nerror: 7 errors found",
     }
 }
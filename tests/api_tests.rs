@@ -0,0 +1,65 @@
+use diamondback::{check, compile, compile_to_asm, parse, CompileError, Options};
+
+fn messages(errors: &[CompileError]) -> Vec<String> {
+    errors.iter().map(|e| e.to_string()).collect()
+}
+
+#[test]
+fn phases_in_process() {
+    let prog = parse("(fun (double x) (* x 2))\n(double input)").unwrap();
+    check(&prog).unwrap();
+    let asm = compile_to_asm(&prog, Options::default()).unwrap();
+    assert!(asm.contains("our_code_starts_here:"));
+    assert!(asm.contains("fun_double:"));
+}
+
+#[test]
+fn parse_reports_every_invalid_form() {
+    let errors = parse("(fun (f x) (let () x))\n(fun (g) (block))\n(f 1)").unwrap_err();
+    assert_eq!(messages(&errors), ["invalid syntax: (let () x)", "invalid syntax: (block)"]);
+}
+
+#[test]
+fn check_reports_every_error_in_order() {
+    let prog = parse("(fun (f x) (+ x y))\n(block (f 1 2) (break 3))").unwrap();
+    let errors = check(&prog).unwrap_err();
+    assert_eq!(
+        messages(&errors),
+        [
+            "unbound variable identifier y",
+            "wrong number of arguments to f: expected 1, found 2",
+            "break outside of a loop",
+        ]
+    );
+    match &errors[0] {
+        CompileError::At(span, _) => assert_eq!((span.start, span.end), (16, 17)),
+        e => panic!("expected a located error, found {:?}", e),
+    }
+}
+
+#[test]
+fn bignum_option() {
+    let prog = parse("(+ input 1)").unwrap();
+    let plain = compile_to_asm(&prog, Options::default()).unwrap();
+    let bignum = compile_to_asm(&prog, Options { bignum: true }).unwrap();
+    assert_ne!(plain, bignum);
+}
+
+#[test]
+fn compile_reports_parse_and_check_errors_together() {
+    let errors = compile("(fun (f) (let () 1))\n(g 1)", Options::default()).unwrap_err();
+    assert_eq!(messages(&errors), ["invalid syntax: (let () 1)", "no such function g"]);
+}
+
+#[test]
+fn programs_do_not_share_state() {
+    let shapes = parse("(data shape (circle r) (rect w h))\n(+ input (match (circle 1) ((circle r) r) (_ 0)))").unwrap();
+    let other = parse("(struct point (x y))\n(point 1 2)").unwrap();
+    assert_eq!(shapes.constructors().collect::<Vec<_>>(), [("circle", 1), ("rect", 2)]);
+    assert_eq!(other.constructors().collect::<Vec<_>>(), [("point", 2)]);
+    let errors = parse("(match 1 ((circle r) r) (_ 0))").unwrap_err();
+    assert_eq!(messages(&errors), ["unknown constructor circle"]);
+    let bignum = compile_to_asm(&shapes, Options { bignum: true }).unwrap();
+    assert_eq!(compile_to_asm(&shapes, Options { bignum: true }).unwrap(), bignum);
+    assert_ne!(compile_to_asm(&shapes, Options::default()).unwrap(), bignum);
+}
@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use diamondback::driver::{build, BuildError};
+use diamondback::Options;
+
+fn out_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+#[test]
+fn build_links_a_runnable_program() {
+    let prog = out_path("driver_fact.run");
+    let src = std::fs::read_to_string("tests/fact.snek").unwrap();
+    build(&src, Options::default(), None, &prog).unwrap();
+    let output = Command::new(&prog).arg("5").output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "120");
+}
+
+#[test]
+fn build_reports_compile_errors() {
+    match build("(+ x 1)", Options::default(), None, &out_path("driver_unbound.run")) {
+        Err(BuildError::Compile(errors)) => assert_eq!(errors[0].to_string(), "unbound variable identifier x"),
+        r => panic!("expected compile errors, found {:?}", r),
+    }
+}
+
+#[test]
+fn build_reports_missing_runtime() {
+    let runtime = out_path("no_such_runtime.rs");
+    match build("1", Options::default(), Some(&runtime), &out_path("driver_missing.run")) {
+        Err(BuildError::RuntimeNotFound(path)) => assert_eq!(path, runtime),
+        r => panic!("expected a missing runtime, found {:?}", r),
+    }
+}
+
+#[test]
+fn build_with_another_runtime() {
+    let runtime = out_path("answer_runtime.rs");
+    std::fs::write(&runtime, "#[link(name = \"our_code\")]\nextern \"C\" {}\n\npub fn main() {\n    println!(\"42\");\n}\n").unwrap();
+    let prog = out_path("driver_answer.run");
+    build("1", Options::default(), Some(&runtime), &prog).unwrap();
+    let output = Command::new(&prog).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "42");
+}
+
+#[test]
+fn build_reports_tool_failures() {
+    let runtime = out_path("broken_runtime.rs");
+    std::fs::write(&runtime, "pub fn main() { not rust }").unwrap();
+    match build("1", Options::default(), Some(&runtime), &out_path("driver_broken.run")) {
+        Err(e @ BuildError::Tool { step: "runtime build", status: Some(_), .. }) => assert!(e.to_string().starts_with("runtime build failed with status")),
+        r => panic!("expected the runtime build to fail, found {:?}", r),
+    }
+}
+
+#[test]
+fn run_subcommand() {
+    let output = Command::new(env!("CARGO_BIN_EXE_diamondback"))
+        .args(["run", "tests/fact.snek", "6"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "720");
+}
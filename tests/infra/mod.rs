@@ -32,6 +32,7 @@ macro_rules! tests {
                 name: $name:ident,
                 file: $file:literal,
                 $(input: $input:literal,)?
+                $(flags: [$($flag:literal),* $(,)?],)?
                 expected: $expected:literal $(,)?
                 $(" $(tt:$tt)* ")?
             }
@@ -44,8 +45,11 @@ macro_rules! tests {
                 #[allow(unused_assignments, unused_mut)]
                 let mut input = None;
                 $(input = Some($input);)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut flags: Vec<&str> = Vec::new();
+                $(flags = vec![$($flag),*];)?
                 let kind = $crate::infra::TestKind::$kind;
-                $crate::infra::run_test(stringify!($name), $file, input, $expected, kind);
+                $crate::infra::run_test(stringify!($name), $file, input, &flags, $expected, kind);
             }
         )*
     };
@@ -55,19 +59,20 @@ pub(crate) fn run_test(
     name: &str,
     file: &str,
     input: Option<&str>,
+    flags: &[&str],
     expected: &str,
     kind: TestKind,
 ) {
     let file = Path::new("tests").join(file);
     match kind {
-        TestKind::Success => run_success_test(name, &file, expected, input),
-        TestKind::RuntimeError => run_runtime_error_test(name, &file, expected, input),
-        TestKind::StaticError => run_static_error_test(name, &file, expected),
+        TestKind::Success => run_success_test(name, &file, flags, expected, input),
+        TestKind::RuntimeError => run_runtime_error_test(name, &file, flags, expected, input),
+        TestKind::StaticError => run_static_error_test(name, &file, flags, expected),
     }
 }
 
-fn run_success_test(name: &str, file: &Path, expected: &str, input: Option<&str>) {
-    if let Err(err) = compile(name, file) {
+fn run_success_test(name: &str, file: &Path, flags: &[&str], expected: &str, input: Option<&str>) {
+    if let Err(err) = compile(name, file, flags) {
         panic!("expected a successful compilation, but got an error: `{err}`");
     }
     match run(name, input) {
@@ -80,8 +85,8 @@ fn run_success_test(name: &str, file: &Path, expected: &str, input: Option<&str>
     }
 }
 
-fn run_runtime_error_test(name: &str, file: &Path, expected: &str, input: Option<&str>) {
-    if let Err(err) = compile(name, file) {
+fn run_runtime_error_test(name: &str, file: &Path, flags: &[&str], expected: &str, input: Option<&str>) {
+    if let Err(err) = compile(name, file, flags) {
         panic!("expected a successful compilation, but got an error: `{err}`");
     }
     match run(name, input) {
@@ -92,8 +97,8 @@ fn run_runtime_error_test(name: &str, file: &Path, expected: &str, input: Option
     }
 }
 
-fn run_static_error_test(name: &str, file: &Path, expected: &str) {
-    match compile(name, file) {
+fn run_static_error_test(name: &str, file: &Path, flags: &[&str], expected: &str) {
+    match compile(name, file, flags) {
         Ok(()) => {
             panic!(
                 "expected a static error, but compilation succeeded - expected error: `{expected}`"
@@ -103,15 +108,18 @@ fn run_static_error_test(name: &str, file: &Path, expected: &str) {
     }
 }
 
-fn compile(name: &str, file: &Path) -> Result<(), String> {
+fn compile(name: &str, file: &Path, flags: &[&str]) -> Result<(), String> {
     // Run the compiler
     let compiler: PathBuf = ["target", "debug", env!("CARGO_PKG_NAME")].iter().collect();
     let output = Command::new(&compiler)
         .arg(file)
         .arg(&mk_path(name, Ext::Asm))
+        .args(flags)
         .output()
         .expect("could not run the compiler");
     if !output.status.success() {
+        // rejected programs exit with 2; anything else is a crash of the compiler
+        assert_eq!(output.status.code(), Some(2), "the compiler crashed: {}", String::from_utf8_lossy(&output.stderr));
         return Err(String::from_utf8(output.stderr).unwrap());
     }
 